@@ -1,5 +1,3 @@
-var app = Elm.Main.init({ node: document.getElementById("elm") })
-
 $(document).ready(() => {
     $("#sidebar")
         .mCustomScrollbar({
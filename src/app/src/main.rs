@@ -6,25 +6,85 @@ extern crate tokio_postgres;
 
 use db2_project_reports::prelude::*;
 use realm::prelude::*;
+use serde_json::Value;
+use std::path::Path;
 
-macro_rules! include_public_str {
-    ($source:literal) => {
-        include_str!(concat!("../public/", $source))
-    };
-}
+const ROOT: &str = env!("CARGO_MANIFEST_DIR");
 
-macro_rules! include_dist_str {
-    ($source:literal) => {
-        include_str!(concat!("../dist/", $source))
-    };
+fn shell(custom_css: &str, elm: &str, custom_js: &str) -> Shell {
+    Shell::new()
+        .link_stylesheet(
+            "https://cdn.jsdelivr.net/npm/bootstrap-themes@^1.0.2/dist/cable/index.min.css",
+            None,
+        )
+        .style(custom_css)
+        .link_stylesheet(
+            "https://cdnjs.cloudflare.com/ajax/libs/malihu-custom-scrollbar-plugin/3.1.5/jquery.mCustomScrollbar.min.css",
+            None,
+        )
+        .script(elm)
+        .link_script(
+            "https://code.jquery.com/jquery-3.5.1.slim.min.js",
+            Some("sha384-DfXdz2htPH0lsSSs5nCTpuj/zy4C+OGpamoFVy38MVBnE+IbbVYUew+OrCXaRkfj"),
+        )
+        .link_script(
+            "https://cdnjs.cloudflare.com/ajax/libs/malihu-custom-scrollbar-plugin/3.1.5/jquery.mCustomScrollbar.concat.min.js",
+            None,
+        )
+        .link_script(
+            "https://cdn.jsdelivr.net/npm/bootstrap@4.5.3/dist/js/bootstrap.bundle.min.js",
+            Some("sha384-ho+j7jyWK8fNQe+A12Hb8AhRq26LrZ/JpcUGGOn+Y7RsweNrtN/tE3MoK7ZeZDyx"),
+        )
+        .init(Value::Null)
+        .script(custom_js)
 }
 
-fn inline_style(s: &str) -> String {
-    format!(r#"<style type="text/css">{}</style>"#, s)
+/// Renders the page from the files on disk, for `APP_DEV` hot reload.
+fn page_from_disk() -> std::io::Result<String> {
+    let read = |path: &str| std::fs::read_to_string(Path::new(ROOT).join(path));
+    let shell = shell(
+        &read("public/custom.css")?,
+        &read("dist/elm.js")?,
+        &read("public/custom.js")?,
+    );
+    Ok(shell.render())
 }
 
-fn inline_script(s: &str) -> String {
-    format!(r#"<script type="text/javascript">{}</script>"#, s)
+/// Embeds the stylesheets and scripts fetched with `cargo make vendor`
+/// instead of linking to the CDNs, so the app renders offline.
+#[cfg(feature = "offline")]
+fn offline(app: AppBuilder<'_, String>) -> AppBuilder<'_, String> {
+    app.asset(
+        "bootstrap.css",
+        realm::asset!("../public/vendor/bootstrap.min.css"),
+    )
+    .asset("custom.css", realm::asset!("../public/custom.css"))
+    .asset(
+        "scrollbar.css",
+        realm::asset!("../public/vendor/jquery.mCustomScrollbar.min.css"),
+    )
+    .asset(
+        "mCSB_buttons.png",
+        realm::asset!("../public/vendor/mCSB_buttons.png"),
+    )
+    .asset("elm.js", realm::asset!("../dist/elm.js"))
+    .asset(
+        "jquery.js",
+        realm::asset!("../public/vendor/jquery.slim.min.js"),
+    )
+    .asset(
+        "scrollbar.js",
+        realm::asset!("../public/vendor/jquery.mCustomScrollbar.concat.min.js"),
+    )
+    .asset(
+        "bootstrap.js",
+        realm::asset!("../public/vendor/bootstrap.bundle.min.js"),
+    )
+    .shell(
+        Shell::new()
+            .init(Value::Null)
+            .script(include_str!("../public/custom.js")),
+    )
 }
 
 #[actix_rt::main]
@@ -32,48 +92,56 @@ async fn main() {
     env_logger::init();
     dotenv::dotenv().ok();
 
-    let file_content = format!(
-        r#"
-        <html>
-            <head>
-                <meta name="viewport" content="width=device-width, initial-scale=1.0">
-                <meta charset="UTF-8">
-                <meta name="viewport" content="width=device-width, initial-scale=1">
-                <link rel="stylesheet" href="https://cdn.jsdelivr.net/npm/bootstrap-themes@^1.0.2/dist/cable/index.min.css">
-                {custom_css}
-                <link rel="stylesheet" href="https://cdnjs.cloudflare.com/ajax/libs/malihu-custom-scrollbar-plugin/3.1.5/jquery.mCustomScrollbar.min.css">
-            </head>
-            <body>
-                <div id="elm"></div>
-                {js}
-                <script src="https://code.jquery.com/jquery-3.5.1.slim.min.js" integrity="sha384-DfXdz2htPH0lsSSs5nCTpuj/zy4C+OGpamoFVy38MVBnE+IbbVYUew+OrCXaRkfj" crossorigin="anonymous"></script>
-                <script src="https://cdnjs.cloudflare.com/ajax/libs/malihu-custom-scrollbar-plugin/3.1.5/jquery.mCustomScrollbar.concat.min.js"></script>
-                <script src="https://cdn.jsdelivr.net/npm/bootstrap@4.5.3/dist/js/bootstrap.bundle.min.js" integrity="sha384-ho+j7jyWK8fNQe+A12Hb8AhRq26LrZ/JpcUGGOn+Y7RsweNrtN/tE3MoK7ZeZDyx" crossorigin="anonymous"></script>
-                {init}
-            </body>
-        </html>
-        "#,
-        custom_css = inline_style(include_public_str!("custom.css")),
-        js = inline_script(include_dist_str!("elm.js")),
-        init = inline_script(include_public_str!("custom.js"))
-    );
-    builder(1)
-        .load(actor(
-            start_client(
-                "host = localhost user = syfers password = KHearts358/2 dbname = db2database",
-            )
-            .await,
-        ))
+    let client =
+        start_client("host = localhost user = syfers password = KHearts358/2 dbname = db2database")
+            .await;
+    let app = builder::<String>(1)
+        .load(closable(client.clone()))
+        .load(metered(client))
         .load(api_init_with(|addr| async move {
-                web_view_api(
-                    "api/report/ranking",
-                    report::<String, _>("SELECT $1", params!(String), addr).await,
-                )
+            web_view_api(
+                "api/report/ranking",
+                report::<String, _>("SELECT $1", params!(String), addr).await,
+            )
         }))
-        .await
-        .content(Content::Html(file_content))
-        .size(1366, 768)
-        .finish()
-        .run()
-        .ok();
+        .await;
+    let app = match app {
+        Ok(app) => app
+            .shell(shell(
+                include_str!("../public/custom.css"),
+                include_str!("../dist/elm.js"),
+                include_str!("../public/custom.js"),
+            ))
+            .size(1366, 768),
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+    #[cfg(feature = "offline")]
+    let app = offline(app);
+
+    let app = match std::env::var("APP_DEV") {
+        Ok(_) => app.hot_reload(
+            HotReload::new(page_from_disk)
+                .watch(Path::new(ROOT).join("public/custom.css"))
+                .watch(Path::new(ROOT).join("dist/elm.js"))
+                .watch(Path::new(ROOT).join("public/custom.js")),
+        ),
+        Err(_) => app,
+    };
+
+    match std::env::var("APP_HTTP_ADDR") {
+        Ok(addr) => match app.http(addr).finish() {
+            Ok(app) => match app.run().await {
+                Ok(report) => tracing::info!(?report, "app closed"),
+                Err(err) => eprintln!("{}", err),
+            },
+            Err(err) => eprintln!("{}", err),
+        },
+        Err(_) => match app.finish().run().await {
+            Ok(report) => tracing::info!(?report, "app closed"),
+            Err(err) => eprintln!("{}", err),
+        },
+    }
 }
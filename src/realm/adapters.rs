@@ -1,3 +1,18 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::future::ready;
+use futures::future::try_join;
+use futures::future::BoxFuture;
+use futures::prelude::*;
+use serde::Serialize;
+
+use crate::webview::prelude::ApiError;
+use crate::webview::ErrorKind;
 use crate::Api;
 
 pub struct AndThen<A, F> {
@@ -19,10 +34,408 @@ where
     }
 }
 
+pub struct MapInput<A, F, I> {
+    f: F,
+    api: A,
+    input: PhantomData<fn(I)>,
+}
+
+impl<A, F, I> Api for MapInput<A, F, I>
+where
+    A: Api,
+    F: Fn(I) -> A::Input,
+{
+    type Input = I;
+    type Output = A::Output;
+
+    fn handle(&self, msg: Self::Input) -> Self::Output {
+        let ref map = self.f;
+        self.api.handle(map(msg))
+    }
+}
+
+pub struct MapErr<A, F> {
+    f: Arc<F>,
+    api: A,
+}
+
+impl<A, F, T, E, U> Api for MapErr<A, F>
+where
+    A: Api,
+    A::Output: Future<Output = Result<T, E>> + Send + 'static,
+    F: Fn(E) -> U + Send + Sync + 'static,
+{
+    type Input = A::Input;
+    type Output = BoxFuture<'static, Result<T, U>>;
+
+    fn handle(&self, msg: Self::Input) -> Self::Output {
+        let map = self.f.clone();
+        self.api
+            .handle(msg)
+            .map(move |result| result.map_err(|err| map(err)))
+            .boxed()
+    }
+}
+
+pub struct OrElse<A, F> {
+    f: Arc<F>,
+    api: A,
+}
+
+impl<A, F, Fut, T, E, U> Api for OrElse<A, F>
+where
+    A: Api,
+    A::Output: Future<Output = Result<T, E>> + Send + 'static,
+    F: Fn(E) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<T, U>> + Send + 'static,
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    type Input = A::Input;
+    type Output = BoxFuture<'static, Result<T, U>>;
+
+    fn handle(&self, msg: Self::Input) -> Self::Output {
+        let recover = self.f.clone();
+        self.api
+            .handle(msg)
+            .then(move |result| match result {
+                Ok(output) => ready(Ok(output)).left_future(),
+                Err(err) => recover(err).right_future(),
+            })
+            .boxed()
+    }
+}
+
+/// Checks the input before handing it to the api, which is not called
+/// at all when the check fails.
+pub struct Validate<A, F> {
+    f: F,
+    api: A,
+}
+
+impl<A, F, T, E> Api for Validate<A, F>
+where
+    A: Api,
+    A::Output: Future<Output = Result<T, E>> + Send + 'static,
+    F: Fn(&A::Input) -> Result<(), E>,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    type Input = A::Input;
+    type Output = BoxFuture<'static, Result<T, E>>;
+
+    fn handle(&self, msg: Self::Input) -> Self::Output {
+        let ref check = self.f;
+        match check(&msg) {
+            Ok(()) => self.api.handle(msg).boxed(),
+            Err(err) => ready(Err(err)).boxed(),
+        }
+    }
+}
+
+/// Turns an output that fails the check into an error.
+pub struct Filter<A, F> {
+    f: Arc<F>,
+    api: A,
+}
+
+impl<A, F, T, E> Api for Filter<A, F>
+where
+    A: Api,
+    A::Output: Future<Output = Result<T, E>> + Send + 'static,
+    F: Fn(&T) -> Result<(), E> + Send + Sync + 'static,
+{
+    type Input = A::Input;
+    type Output = BoxFuture<'static, Result<T, E>>;
+
+    fn handle(&self, msg: Self::Input) -> Self::Output {
+        let check = self.f.clone();
+        self.api
+            .handle(msg)
+            .map(move |result| result.and_then(|output| check(&output).map(|_| output)))
+            .boxed()
+    }
+}
+
+pub struct Timeout<A> {
+    duration: Duration,
+    api: A,
+}
+
+impl<A, T, E> Api for Timeout<A>
+where
+    A: Api,
+    A::Output: Future<Output = Result<T, E>> + Send + 'static,
+    E: From<ApiError>,
+{
+    type Input = A::Input;
+    type Output = BoxFuture<'static, Result<T, E>>;
+
+    fn handle(&self, msg: Self::Input) -> Self::Output {
+        tokio::time::timeout(self.duration, self.api.handle(msg))
+            .map(|result| {
+                result.unwrap_or_else(|_| Err(ErrorKind::Timeout.error("Api timed out").into()))
+            })
+            .boxed()
+    }
+}
+
+/// How many times an api is tried, and how long to wait between tries.
+/// The wait is multiplied by the backoff factor after every try.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    attempts: u32,
+    delay: Duration,
+    factor: u32,
+}
+
+impl RetryPolicy {
+    pub fn attempts(attempts: u32) -> Self {
+        RetryPolicy {
+            attempts,
+            delay: Duration::from_millis(0),
+            factor: 1,
+        }
+    }
+
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn backoff(mut self, factor: u32) -> Self {
+        self.factor = factor;
+        self
+    }
+
+    pub fn should_retry(&self, attempt: u32) -> bool {
+        attempt + 1 < self.attempts
+    }
+
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        self.delay
+            .checked_mul(self.factor.saturating_pow(attempt))
+            .unwrap_or(Duration::from_secs(u64::MAX))
+    }
+}
+
+pub struct Retry<A> {
+    policy: RetryPolicy,
+    api: Arc<A>,
+}
+
+impl<A, T, E> Api for Retry<A>
+where
+    A: Api + Send + Sync + 'static,
+    A::Input: Clone + Send + 'static,
+    A::Output: Future<Output = Result<T, E>> + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    type Input = A::Input;
+    type Output = BoxFuture<'static, Result<T, E>>;
+
+    fn handle(&self, msg: Self::Input) -> Self::Output {
+        let api = self.api.clone();
+        let policy = self.policy;
+        async move {
+            let mut attempt = 0;
+            loop {
+                match api.handle(msg.clone()).await {
+                    Err(_) if policy.should_retry(attempt) => {
+                        tokio::time::delay_for(policy.delay_for(attempt)).await;
+                        attempt += 1;
+                    }
+                    result => return result,
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Remembers successful outputs by their serialized input for `ttl`.
+pub struct Cache<A, T> {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<String, (Instant, T)>>>,
+    api: A,
+}
+
+impl<A, T> Cache<A, T> {
+    fn lookup(&self, key: &str) -> Option<T>
+    where
+        T: Clone,
+    {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((at, output)) if at.elapsed() < self.ttl => Some(output.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+impl<A, T, E> Api for Cache<A, T>
+where
+    A: Api,
+    A::Input: Serialize,
+    A::Output: Future<Output = Result<T, E>> + Send + 'static,
+    T: Clone + Send + 'static,
+    E: Send + 'static,
+{
+    type Input = A::Input;
+    type Output = BoxFuture<'static, Result<T, E>>;
+
+    fn handle(&self, msg: Self::Input) -> Self::Output {
+        let key = serde_json::to_string(&msg).ok();
+        if let Some(output) = key.as_ref().and_then(|key| self.lookup(key)) {
+            return ready(Ok(output)).boxed();
+        }
+        let entries = self.entries.clone();
+        self.api
+            .handle(msg)
+            .map(move |result| {
+                if let (Ok(output), Some(key)) = (&result, key) {
+                    let entry = (Instant::now(), output.clone());
+                    entries.lock().unwrap().insert(key, entry);
+                }
+                result
+            })
+            .boxed()
+    }
+}
+
+/// Hands the input to another api when the first one fails.
+pub struct Fallback<A, B> {
+    api: A,
+    fallback: Arc<B>,
+}
+
+impl<A, B, T, E> Api for Fallback<A, B>
+where
+    A: Api,
+    A::Input: Clone + Send + 'static,
+    A::Output: Future<Output = Result<T, E>> + Send + 'static,
+    B: Api<Input = A::Input> + Send + Sync + 'static,
+    B::Output: Future<Output = Result<T, E>> + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    type Input = A::Input;
+    type Output = BoxFuture<'static, Result<T, E>>;
+
+    fn handle(&self, msg: Self::Input) -> Self::Output {
+        let fallback = self.fallback.clone();
+        let input = msg.clone();
+        self.api
+            .handle(msg)
+            .then(move |result| match result {
+                Ok(output) => ready(Ok(output)).left_future(),
+                Err(_) => fallback.handle(input).right_future(),
+            })
+            .boxed()
+    }
+}
+
+/// Runs two apis concurrently on the same input, answering both outputs
+/// or the first error.
+pub struct Zip<A, B> {
+    api: A,
+    other: B,
+}
+
+impl<A, B, T, U, E> Api for Zip<A, B>
+where
+    A: Api,
+    A::Input: Clone,
+    A::Output: Future<Output = Result<T, E>> + Send + 'static,
+    B: Api<Input = A::Input>,
+    B::Output: Future<Output = Result<U, E>> + Send + 'static,
+    T: Send + 'static,
+    U: Send + 'static,
+    E: Send + 'static,
+{
+    type Input = A::Input;
+    type Output = BoxFuture<'static, Result<(T, U), E>>;
+
+    fn handle(&self, msg: Self::Input) -> Self::Output {
+        try_join(self.api.handle(msg.clone()), self.other.handle(msg)).boxed()
+    }
+}
+
 pub trait ApiAdapters: Api + Sized {
     fn and_then<U, F: Fn(Self::Output) -> U>(self, f: F) -> AndThen<Self, F> {
         AndThen { api: self, f }
     }
+
+    fn map_input<I, F: Fn(I) -> Self::Input>(self, f: F) -> MapInput<Self, F, I> {
+        MapInput {
+            api: self,
+            f,
+            input: PhantomData,
+        }
+    }
+
+    fn map_err<F>(self, f: F) -> MapErr<Self, F> {
+        MapErr {
+            api: self,
+            f: Arc::new(f),
+        }
+    }
+
+    fn or_else<F>(self, f: F) -> OrElse<Self, F> {
+        OrElse {
+            api: self,
+            f: Arc::new(f),
+        }
+    }
+
+    fn validate<F>(self, f: F) -> Validate<Self, F> {
+        Validate { api: self, f }
+    }
+
+    fn filter<F>(self, f: F) -> Filter<Self, F> {
+        Filter {
+            api: self,
+            f: Arc::new(f),
+        }
+    }
+
+    fn timeout(self, duration: Duration) -> Timeout<Self> {
+        Timeout {
+            api: self,
+            duration,
+        }
+    }
+
+    fn retry(self, policy: RetryPolicy) -> Retry<Self> {
+        Retry {
+            api: Arc::new(self),
+            policy,
+        }
+    }
+
+    fn cache<T>(self, ttl: Duration) -> Cache<Self, T> {
+        Cache {
+            api: self,
+            ttl,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn fallback<B: Api<Input = Self::Input>>(self, fallback: B) -> Fallback<Self, B> {
+        Fallback {
+            api: self,
+            fallback: Arc::new(fallback),
+        }
+    }
+
+    fn zip<B: Api<Input = Self::Input>>(self, other: B) -> Zip<Self, B> {
+        Zip { api: self, other }
+    }
 }
 
 impl<S> ApiAdapters for S where S: Api {}
@@ -1,35 +1,67 @@
+use std::collections::HashMap;
 use std::future::ready;
 use std::marker::PhantomData;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
 use actix::dev::MessageResponse;
 use actix::prelude::*;
+use futures::channel::oneshot;
+use futures::future::AbortHandle;
+use futures::future::Abortable;
+use futures::future::Shared;
 use futures::prelude::*;
 use serde::de::DeserializeOwned;
-use serde::Serialize;
 use serde_json::json;
+use serde_json::Value;
 
+use crate::adapters::RetryPolicy;
 use crate::Api;
 
+use super::container::ActorContainer;
+use super::context::Contextual;
+use super::describe::ApiSchema;
+use super::describe::Describe;
+use super::devtools::report_lazy;
+use super::devtools::DevTools;
+use super::devtools::LazyState;
 use super::error;
+use super::errors::failure;
+use super::errors::ApiError;
+use super::errors::ApiOutput;
+use super::stream::Streaming;
 use super::success;
+use super::supervise::catch_panic;
+use super::supervise::defer_panic;
+use super::supervise::panicked;
+use super::supervise::Restart;
+use super::supervise::StatusBoard;
+use super::supervise::Supervision;
+use super::ApiResponse;
+use super::Cancel;
+use super::ErrorKind;
 use super::InvokeBody;
 use super::InvokeRequest;
 use super::KeyedActor;
+use super::RequestId;
 
 pub enum LazyResponse<A> {
-    InitResponse(AtomicResponse<A, ()>),
-    NormalResponse(ResponseActFuture<A, ()>),
+    InitResponse(AtomicResponse<A, ApiResponse>),
+    NormalResponse(ResponseActFuture<A, ApiResponse>),
 }
 
 impl<A> LazyResponse<A>
 where
     A: Actor,
 {
-    pub fn init<F: ActorFuture<Actor = A, Output = ()> + 'static>(f: F) -> Self {
+    pub fn init<F: ActorFuture<Actor = A, Output = ApiResponse> + 'static>(f: F) -> Self {
         Self::InitResponse(AtomicResponse::new(Box::pin(f)))
     }
 
-    pub fn normal<F: ActorFuture<Actor = A, Output = ()> + 'static>(f: F) -> Self {
+    pub fn normal<F: ActorFuture<Actor = A, Output = ApiResponse> + 'static>(f: F) -> Self {
         Self::NormalResponse(Box::pin(f))
     }
 }
@@ -37,7 +69,7 @@ where
 impl<A, M> MessageResponse<A, M> for LazyResponse<A>
 where
     A: Actor,
-    M: Message<Result = ()>,
+    M: Message<Result = ApiResponse>,
     A::Context: AsyncContext<A>,
 {
     fn handle<R: dev::ResponseChannel<M>>(self, ctx: &mut A::Context, tx: Option<R>) {
@@ -51,6 +83,22 @@ where
 pub struct WebViewApi<API> {
     pub(crate) api_key: &'static str,
     pub(crate) api: API,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) in_flight: HashMap<RequestId, AbortHandle>,
+    pub(crate) restart: Restart,
+    pub(crate) restarts: u32,
+}
+
+impl<API> WebViewApi<API> {
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn restart(mut self, restart: Restart) -> Self {
+        self.restart = restart;
+        self
+    }
 }
 
 impl<API> Actor for WebViewApi<API>
@@ -60,61 +108,287 @@ where
     type Context = Context<Self>;
 }
 
+impl<API> Supervised for WebViewApi<API>
+where
+    API: Unpin + 'static,
+{
+    fn restarting(&mut self, _: &mut Self::Context) {
+        for (_, abort) in self.in_flight.drain() {
+            abort.abort();
+        }
+    }
+}
+
 impl<API> Handler<InvokeRequest> for WebViewApi<API>
 where
     API: Api + Unpin + 'static,
     API::Input: DeserializeOwned,
     API::Output: Future + Send,
-    <API::Output as Future>::Output: Serialize,
+    <API::Output as Future>::Output: ApiOutput,
 {
-    type Result = ResponseActFuture<Self, ()>;
+    type Result = ResponseActFuture<Self, ApiResponse>;
 
     fn handle(&mut self, msg: InvokeRequest, _: &mut Self::Context) -> Self::Result {
-        use serde_json::Value::*;
         let InvokeRequest {
-            body: InvokeBody { api_name, payload },
-            caller,
-            data: _,
+            body:
+                InvokeBody {
+                    id,
+                    api_name,
+                    payload,
+                },
+            caller: _,
+            data,
+            route,
+            ..
         } = msg;
+        let (name, request_id) = (api_name.clone(), id.clone());
         let result = serde_json::from_value(payload.clone());
+        let api = &self.api;
         let handler = match result {
-            Ok(result) => self
-                .api
-                .handle(result)
-                .then(move |ref result| {
-                    let value = serde_json::to_value(result).unwrap();
-                    let send = match value {
-                        Object(map) if map.contains_key("Err") => {
-                            serde_json::to_string(&error(api_name, result)).unwrap()
-                        }
-                        _ => serde_json::to_string(&success(api_name, result)).unwrap(),
-                    };
-                    caller.do_send(send.into()).ok();
-                    ready(())
+            Ok(result) => defer_panic(|| api.handle(result))
+                .map(move |output| match output.into_result() {
+                    Ok(payload) => success(api_name, id, &payload),
+                    Err(err) => error(api_name, id, &err),
                 })
                 .right_future(),
-            Err(e) => {
-                let error = serde_json::to_string(&error(
-                    api_name,
-                    &json!({
-                        "error": "Malformed payload",
-                        "found": payload.to_string(),
-                        "description": e.to_string()
-                    }),
-                ))
-                .unwrap();
-                caller.do_send(error.into()).ok();
-                ready(()).left_future()
+            Err(e) => ready(malformed_payload(api_name, id, &payload, e)).left_future(),
+        };
+        self.track(name, route, request_id, &data, handler)
+    }
+}
+
+impl<API> WebViewApi<API>
+where
+    API: Unpin + 'static,
+{
+    pub(crate) fn track<Fut>(
+        &mut self,
+        name: String,
+        route: String,
+        request_id: Option<RequestId>,
+        container: &ActorContainer,
+        handler: Fut,
+    ) -> ResponseActFuture<Self, ApiResponse>
+    where
+        Fut: Future<Output = ApiResponse> + 'static,
+    {
+        let duplicate = request_id
+            .as_ref()
+            .filter(|id| self.in_flight.contains_key(id));
+        if let Some(id) = duplicate.cloned() {
+            let response = failure(name, Some(id), ErrorKind::Request, "Duplicate request id");
+            return Box::pin(ready(response).into_actor(self));
+        }
+        let status = container.get::<StatusBoard>();
+        let handler = catch_panic(handler);
+        let handler = match self.timeout {
+            Some(timeout) => {
+                let (api_name, id) = (name.clone(), request_id.clone());
+                tokio::time::timeout(timeout, handler)
+                    .map(move |res| {
+                        res.unwrap_or_else(|_| {
+                            Ok(failure(
+                                api_name,
+                                id,
+                                ErrorKind::Timeout,
+                                "Request timed out",
+                            ))
+                        })
+                    })
+                    .left_future()
             }
+            None => handler.right_future(),
         };
-        Box::pin(handler.into_actor(self))
+        let handler = match request_id {
+            Some(ref id) => {
+                let (api_name, id) = (name.clone(), id.clone());
+                let (abort, registration) = AbortHandle::new_pair();
+                self.in_flight.insert(id.clone(), abort);
+                Abortable::new(handler, registration)
+                    .map(move |res| {
+                        res.unwrap_or_else(|_| {
+                            Ok(failure(
+                                api_name,
+                                Some(id),
+                                ErrorKind::Cancelled,
+                                "Request cancelled",
+                            ))
+                        })
+                    })
+                    .left_future()
+            }
+            None => handler.right_future(),
+        };
+        Box::pin(handler.into_actor(self).map(move |res, actor, ctx| {
+            if let Some(ref id) = request_id {
+                actor.in_flight.remove(id);
+            }
+            res.unwrap_or_else(|message| {
+                actor.supervise(&message, route, status, ctx);
+                panicked(name, request_id, &message)
+            })
+        }))
+    }
+
+    /// Reports a panic under the route the request matched, and stops the
+    /// actor for its `Supervisor` to restart it when the `Restart` policy
+    /// allows.
+    fn supervise(
+        &mut self,
+        message: &str,
+        route: String,
+        status: Option<Addr<StatusBoard>>,
+        ctx: &mut Context<Self>,
+    ) {
+        let restart = self.restart.allows(self.restarts);
+        if let Some(status) = status {
+            status.do_send(Supervision::Panicked(route.clone(), message.into()));
+            if restart {
+                status.do_send(Supervision::Restarted(route));
+            }
+        }
+        if restart {
+            self.restarts += 1;
+            ctx.stop();
+        }
+    }
+}
+
+impl<API> Handler<Cancel> for WebViewApi<API>
+where
+    API: Unpin + 'static,
+{
+    type Result = bool;
+
+    fn handle(&mut self, Cancel(id): Cancel, _: &mut Self::Context) -> Self::Result {
+        self.in_flight
+            .remove(&id)
+            .map(|abort| abort.abort())
+            .is_some()
+    }
+}
+
+/// What a `lazy` factory resolves to: the api itself, or a `Result` whose
+/// error fails the request that triggered the initialization.
+pub trait LazyOutput {
+    type Api;
+
+    fn into_api(self) -> Result<Self::Api, ApiError>;
+}
+
+impl<API: Api> LazyOutput for API {
+    type Api = API;
+
+    fn into_api(self) -> Result<Self::Api, ApiError> {
+        Ok(self)
+    }
+}
+
+impl<API> LazyOutput for Streaming<API> {
+    type Api = Self;
+
+    fn into_api(self) -> Result<Self::Api, ApiError> {
+        Ok(self)
+    }
+}
+
+impl<API> LazyOutput for Contextual<API> {
+    type Api = Self;
+
+    fn into_api(self) -> Result<Self::Api, ApiError> {
+        Ok(self)
+    }
+}
+
+impl<API, E: Into<ApiError>> LazyOutput for Result<API, E> {
+    type Api = API;
+
+    fn into_api(self) -> Result<Self::Api, ApiError> {
+        self.map_err(Into::into)
+    }
+}
+
+/// Makes every `lazy` api holding a clone run its factory again on its
+/// next request.
+#[derive(Clone, Default)]
+pub struct Invalidator(Arc<AtomicU64>);
+
+impl Invalidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn invalidate(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn generation(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
     }
 }
 
 pub struct WebViewLazyApi<F> {
     pub(crate) api_key: &'static str,
     pub(crate) api: Option<Recipient<InvokeRequest>>,
-    pub(crate) factory: F,
+    pub(crate) canceller: Option<Recipient<Cancel>>,
+    pub(crate) factory: Arc<F>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) retry: RetryPolicy,
+    pub(crate) idle: Option<Duration>,
+    pub(crate) invalidator: Option<Invalidator>,
+    pub(crate) generation: u64,
+    pub(crate) last_used: Instant,
+    pub(crate) restart: Restart,
+    pub(crate) restarts: u32,
+    pub(crate) initializing: Option<Shared<oneshot::Receiver<()>>>,
+    pub(crate) init_abort: Option<(RequestId, AbortHandle)>,
+}
+
+impl<F> WebViewLazyApi<F> {
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Runs a failing factory again following `policy` before failing the
+    /// request. Requests that arrive meanwhile wait for the outcome.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Drops the api once it was not called for `idle`.
+    pub fn idle(mut self, idle: Duration) -> Self {
+        self.idle = Some(idle);
+        self
+    }
+
+    pub fn invalidated_by(mut self, invalidator: &Invalidator) -> Self {
+        self.invalidator = Some(invalidator.clone());
+        self
+    }
+
+    pub fn restart(mut self, restart: Restart) -> Self {
+        self.restart = restart;
+        self
+    }
+
+    fn reset(&mut self) {
+        self.api = None;
+        self.canceller = None;
+    }
+
+    fn is_stale(&self) -> bool {
+        let idle = match self.idle {
+            Some(idle) => self.last_used.elapsed() >= idle,
+            None => false,
+        };
+        let invalidated = match self.invalidator {
+            Some(ref invalidator) => invalidator.generation() != self.generation,
+            None => false,
+        };
+        idle || invalidated
+    }
 }
 
 impl<F> Actor for WebViewLazyApi<F>
@@ -124,58 +398,276 @@ where
     type Context = Context<Self>;
 }
 
-impl<F, API, Fut> Handler<InvokeRequest> for WebViewLazyApi<F>
+impl<F> Supervised for WebViewLazyApi<F>
+where
+    F: Unpin + 'static,
+{
+    fn restarting(&mut self, _: &mut Self::Context) {
+        self.reset();
+    }
+}
+
+impl<F> WebViewLazyApi<F>
+where
+    F: Unpin + 'static,
+{
+    /// Drops an api that panicked, for the factory to build it again, when
+    /// the `Restart` policy allows.
+    fn supervise(
+        &mut self,
+        response: &ApiResponse,
+        route: String,
+        status: Option<Addr<StatusBoard>>,
+        devtools: Option<Addr<DevTools>>,
+    ) {
+        self.last_used = Instant::now();
+        if response.kind() != Some(ErrorKind::Panic) || !self.restart.allows(self.restarts) {
+            return;
+        }
+        self.restarts += 1;
+        self.reset();
+        report_lazy(&devtools, self.api_key, LazyState::Dropped);
+        if let Some(status) = status {
+            status.do_send(Supervision::Restarted(route));
+        }
+    }
+}
+
+impl<F, Fut> Handler<InvokeRequest> for WebViewLazyApi<F>
 where
-    API: Send,
-    Fut: Future<Output = API> + 'static + Send,
+    Fut: Future + 'static,
+    Fut::Output: LazyOutput,
+    <Fut::Output as LazyOutput>::Api: Send,
     F: Fn(InvokeRequest) -> Fut + Unpin + 'static,
-    WebViewApi<API>: Handler<InvokeRequest>,
-    WebViewApi<API>: Actor<Context = Context<WebViewApi<API>>>,
+    WebViewApi<<Fut::Output as LazyOutput>::Api>: Handler<InvokeRequest> + Handler<Cancel>,
+    WebViewApi<<Fut::Output as LazyOutput>::Api>:
+        Actor<Context = Context<WebViewApi<<Fut::Output as LazyOutput>::Api>>>,
 {
     type Result = LazyResponse<Self>;
 
-    fn handle(&mut self, msg: InvokeRequest, _: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: InvokeRequest, ctx: &mut Self::Context) -> Self::Result {
+        let api_name = msg.body().api_name().clone();
+        let id = msg.body().id().cloned();
+        if let Some(ref initializing) = self.initializing {
+            let addr = ctx.address();
+            return LazyResponse::normal(
+                initializing
+                    .clone()
+                    .then(move |_| addr.send(msg))
+                    .map(move |res| res.unwrap_or_else(|err| mailbox_error(api_name, id, err)))
+                    .into_actor(self),
+            );
+        }
+        let status = msg.data.get::<StatusBoard>();
+        let devtools = msg.data.get::<DevTools>();
+        let route = msg.route.clone();
+        if self.api.is_some() && self.is_stale() {
+            self.reset();
+        }
+        self.last_used = Instant::now();
         if self.api.is_none() {
-            let ref factory = self.factory;
-            let api_key = self.api_key;
-            let future = factory(msg.clone())
-                .map(move |api| WebViewApi { api_key, api }.start().recipient());
-            LazyResponse::init(future.into_actor(self).then(move |res, actor, _| {
-                res.do_send(msg).ok();
-                actor.api = Some(res);
-                async {}.actfuture()
+            report_lazy(&devtools, self.api_key, LazyState::Initializing);
+            let factory = self.factory.clone();
+            let policy = self.retry;
+            let request = msg.clone();
+            let init = catch_panic(async move {
+                let mut attempt = 0;
+                loop {
+                    match factory(request.clone()).await.into_api() {
+                        Err(_) if policy.should_retry(attempt) => {
+                            tokio::time::delay_for(policy.delay_for(attempt)).await;
+                            attempt += 1;
+                        }
+                        result => return result,
+                    }
+                }
+            });
+            let (initialized, initializing) = oneshot::channel();
+            let (abort, registration) = AbortHandle::new_pair();
+            self.initializing = Some(initializing.shared());
+            self.init_abort = id.clone().map(|id| (id, abort));
+            let init = Abortable::new(init, registration);
+            let generation = self.invalidator.as_ref().map_or(0, Invalidator::generation);
+            LazyResponse::normal(init.into_actor(self).then(move |api, actor, _| {
+                drop(initialized);
+                actor.initializing = None;
+                actor.init_abort = None;
+                let response = match api {
+                    Ok(Ok(Ok(api))) => Ok(api),
+                    Ok(Ok(Err(err))) => {
+                        let failed = LazyState::Failed {
+                            error: serde_json::to_value(&err).unwrap_or_default(),
+                        };
+                        report_lazy(&devtools, actor.api_key, failed);
+                        Err(error(api_name.clone(), id.clone(), &err))
+                    }
+                    Ok(Err(message)) => {
+                        let failed = LazyState::Failed {
+                            error: message.clone().into(),
+                        };
+                        report_lazy(&devtools, actor.api_key, failed);
+                        if let Some(ref status) = status {
+                            let panic = Supervision::Panicked(route.clone(), message.clone());
+                            status.do_send(panic);
+                        }
+                        Err(panicked(api_name.clone(), id.clone(), &message))
+                    }
+                    Err(_) => {
+                        let message = "Request cancelled";
+                        let failed = LazyState::Failed {
+                            error: message.into(),
+                        };
+                        report_lazy(&devtools, actor.api_key, failed);
+                        let kind = ErrorKind::Cancelled;
+                        Err(failure(api_name.clone(), id.clone(), kind, message))
+                    }
+                };
+                let api = match response {
+                    Ok(api) => api,
+                    Err(response) => {
+                        let response: ResponseActFuture<Self, ApiResponse> =
+                            Box::pin(ready(response).into_actor(actor));
+                        return response;
+                    }
+                };
+                let addr = WebViewApi {
+                    api_key: actor.api_key,
+                    api,
+                    timeout: actor.timeout,
+                    in_flight: HashMap::new(),
+                    restart: Restart::Never,
+                    restarts: 0,
+                }
+                .start();
+                let res = addr.clone().recipient();
+                actor.api = Some(res.clone());
+                actor.canceller = Some(addr.recipient());
+                actor.generation = generation;
+                report_lazy(&devtools, actor.api_key, LazyState::Ready { generation });
+                Box::pin(
+                    res.send(msg)
+                        .map(move |res| res.unwrap_or_else(|err| mailbox_error(api_name, id, err)))
+                        .into_actor(actor)
+                        .map(move |res, actor, _| {
+                            actor.supervise(&res, route, status, devtools);
+                            res
+                        }),
+                )
             }))
         } else {
             let api = self.api.as_ref().expect("Loaded true but cell empty");
             LazyResponse::normal(
                 api.send(msg)
+                    .map(move |res| res.unwrap_or_else(|err| mailbox_error(api_name, id, err)))
                     .into_actor(self)
-                    .then(|_, _, _| async {}.actfuture()),
+                    .map(move |res, actor, _| {
+                        actor.supervise(&res, route, status, devtools);
+                        res
+                    }),
             )
         }
     }
 }
 
+impl<F> Handler<Cancel> for WebViewLazyApi<F>
+where
+    F: Unpin + 'static,
+{
+    type Result = ResponseFuture<bool>;
+
+    fn handle(&mut self, msg: Cancel, _: &mut Self::Context) -> Self::Result {
+        if let Some((ref id, ref abort)) = self.init_abort {
+            if *id == msg.0 {
+                abort.abort();
+                self.init_abort = None;
+                return Box::pin(ready(true));
+            }
+        }
+        match self.canceller {
+            Some(ref canceller) => Box::pin(canceller.send(msg).map(|res| res.unwrap_or(false))),
+            None => Box::pin(ready(false)),
+        }
+    }
+}
+
 impl<API> KeyedActor for WebViewApi<API>
 where
-    WebViewApi<API>: Actor<Context = Context<WebViewApi<API>>>,
-    WebViewApi<API>: Handler<InvokeRequest>,
+    API: Describe,
+    WebViewApi<API>: Actor<Context = Context<WebViewApi<API>>> + Supervised,
+    WebViewApi<API>: Handler<InvokeRequest> + Handler<Cancel>,
 {
     fn api_key(&self) -> &'static str {
         self.api_key
     }
+
+    fn schema(&self) -> ApiSchema {
+        API::describe()
+    }
 }
 
-impl<F> KeyedActor for WebViewLazyApi<F>
+impl<F, Fut> KeyedActor for WebViewLazyApi<F>
 where
-    WebViewLazyApi<F>: Actor<Context = Context<WebViewLazyApi<F>>>,
-    WebViewLazyApi<F>: Handler<InvokeRequest>,
+    F: Fn(InvokeRequest) -> Fut,
+    Fut: Future,
+    Fut::Output: LazyOutput,
+    <Fut::Output as LazyOutput>::Api: Describe,
+    WebViewLazyApi<F>: Actor<Context = Context<WebViewLazyApi<F>>> + Supervised,
+    WebViewLazyApi<F>: Handler<InvokeRequest> + Handler<Cancel>,
 {
     fn api_key(&self) -> &'static str {
         self.api_key
     }
+
+    fn schema(&self) -> ApiSchema {
+        <Fut::Output as LazyOutput>::Api::describe()
+    }
 }
 
 pub struct WebViewLoadableActor<A: Actor>(pub(crate) Addr<A>);
 
-pub struct WebViewApiFactory<F, ArgDump>(pub(crate) F, pub(crate) PhantomData<ArgDump>);
+pub struct WebViewApiFactory<F, ArgDump>(
+    pub(crate) F,
+    pub(crate) PhantomData<ArgDump>,
+    pub(crate) Option<&'static str>,
+);
+
+impl<F, ArgDump> WebViewApiFactory<F, ArgDump> {
+    /// The key of the api the factory builds, naming it in the
+    /// `BuildError` when its dependencies are missing.
+    pub fn key(mut self, api_key: &'static str) -> Self {
+        self.2 = Some(api_key);
+        self
+    }
+}
+
+/// Marks a factory taking every dependency as a separate argument.
+pub struct Args<T>(PhantomData<T>);
+
+pub(crate) fn malformed_payload(
+    api_name: String,
+    id: Option<RequestId>,
+    payload: &Value,
+    e: serde_json::Error,
+) -> ApiResponse {
+    let details = json!({
+        "found": payload.to_string(),
+        "description": e.to_string()
+    });
+    let err = ErrorKind::Payload
+        .error("Malformed payload")
+        .details(&details);
+    error(api_name, id, &err).with_kind(ErrorKind::Payload)
+}
+
+pub(crate) fn mailbox_error(
+    api_name: String,
+    id: Option<RequestId>,
+    err: MailboxError,
+) -> ApiResponse {
+    failure(
+        api_name,
+        id,
+        ErrorKind::Unreachable,
+        format!("Api unreachable: {}", err),
+    )
+}
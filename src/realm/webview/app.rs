@@ -1,9 +1,17 @@
 use std::mem::MaybeUninit;
+use std::path::PathBuf;
 use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 use actix::prelude::*;
+use futures::future::ready;
+use futures::StreamExt;
 use serde::Serialize;
+use tracing::Instrument;
 use web_view::Content;
 use web_view::Handle;
 use web_view::WVResult;
@@ -13,55 +21,47 @@ use web_view::WebViewBuilder;
 use crate::Application;
 use crate::Load;
 
+use super::api::Args;
 use super::api::WebViewApiFactory;
 use super::api::WebViewLoadableActor;
+use super::assets::Asset;
+use super::assets::Assets;
+use super::container::ActorContainer;
 use super::container::WebViewBuilderContainer;
 use super::container::WebViewContainer;
-use super::error;
+use super::events::EventBus;
+use super::extract::BuildError;
+use super::extract::FromContainer;
+use super::extract::WebViewShared;
+use super::headless::HeadlessBuilder;
+#[cfg(feature = "http")]
+use super::http::HttpBuilder;
+use super::lifecycle::Close;
+use super::lifecycle::ExitReport;
+use super::lifecycle::Lifecycle;
+use super::lifecycle::WebViewClosableActor;
+use super::metrics::Collect;
+use super::metrics::WebViewMeteredActor;
+use super::middleware::Middleware;
+use super::middleware::WebViewMiddleware;
+use super::protocol::Batch;
+use super::protocol::Protocol;
+use super::reload::reload_script;
+use super::reload::HotReload;
+use super::router::Router;
+use super::shell::Shell;
 use super::AppString;
-use super::InvokeBody;
-use super::InvokeRequest;
 use super::KeyedActor;
 
-macro_rules! impl_extractor {
-    ($($name:tt),+) => {
-        impl<'a, Cont, Factory, Fut, $($name),+> Load<WebViewApiFactory<Factory,  (Fut, $($name), +) >> for AppBuilder<'a, Cont>
-        where
-            Factory: Fn($(Addr<$name>),+) -> Fut,
-            Fut: Future + Send + 'a,
-            <Factory::Output as Future>::Output: KeyedActor,
-            Cont: Send + 'a,
-        $(
-            $name: Actor<Context = Context<$name>> + Send + Sync
-        ),+
-        {
-            type Result = Pin<Box<dyn Future<Output = Self> + 'a>>;
-
-            fn load(self, arg: WebViewApiFactory<Factory, ( Fut, $($name),+ )>) -> Self::Result {
-                let ref container = self.container;
-                let ref factory = arg.0;
-                let container = container.actor_container();
-                let api = factory($(container.get::<$name>().unwrap()),+);
-                Box::pin(async move { self.load(api.await) })
-            }
-        }
-    }
-}
-
-impl_extractor!(A);
-impl_extractor!(A, B);
-impl_extractor!(A, B, C);
-impl_extractor!(A, B, C, D);
-impl_extractor!(A, B, C, D, E);
-
 pub struct App {
     handle: Handle<MaybeUninit<Addr<Self>>>,
     container: Arc<WebViewContainer>,
+    arbiter: Arbiter,
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct Received(String);
+pub struct Received(pub(crate) String);
 
 impl From<String> for Received {
     fn from(x: String) -> Self {
@@ -77,7 +77,7 @@ impl Handler<AppString> for App {
     type Result = ();
 
     fn handle(&mut self, msg: AppString, _: &mut Self::Context) -> Self::Result {
-        self.dispatch_to_web_view(msg.0);
+        self.dispatch_to_web_view(self.container.notification(msg.0));
     }
 }
 
@@ -94,40 +94,36 @@ impl App {
     where
         Data: Serialize + Sync + Send + 'static,
     {
-        let value = serde_json::to_string(&body).unwrap();
-        println!("Dispatching: {}", value);
-        self.handle
-            .dispatch(move |wv| wv.eval(&*format!("sendToElm({})", value)))
-            .ok();
+        send_to_elm(&self.handle, body);
     }
 
+    /// Hands the request over to the actix thread, waking the webview
+    /// sharing it so that it is picked up without waiting for a `TICK`.
     pub(crate) fn dispatch_request(&self, param: &str, addr: Addr<Self>) {
-        let invoke_body: Result<InvokeBody, serde_json::Error> = serde_json::from_str(param);
-        let caller = addr.clone().recipient();
-        match invoke_body {
-            Ok(body) => {
-                let api_name = body.api_name().clone();
-                let api = self.container.get(&*api_name);
-                let data = self.container.shared();
-                if let Some(api) = api {
-                    api.do_send(InvokeRequest { body, caller, data }).ok();
-                } else {
-                    let result = error(
-                        "request/not-found".into(),
-                        &format!("Unable to find: {} api", body.api_name),
-                    );
-                    self.dispatch_to_web_view(result);
-                }
-            }
-            Err(err) => {
-                let err_string = err.to_string();
-                let result = error("request/failed".into(), &err_string);
-                self.dispatch_to_web_view(result);
-            }
-        };
+        let caller: Recipient<AppString> = addr.recipient();
+        let handle = self.handle.clone();
+        let span = tracing::debug_span!("message");
+        let responses = span.in_scope(|| self.container.dispatch(param, caller));
+        let dispatch = responses.for_each(move |send| {
+            send_to_elm(&handle, send);
+            ready(())
+        });
+        self.arbiter.send(Box::pin(dispatch.instrument(span)));
+        self.handle.dispatch(|_| Ok(())).ok();
     }
 }
 
+fn send_to_elm<Data>(handle: &Handle<MaybeUninit<Addr<App>>>, body: Data)
+where
+    Data: Serialize,
+{
+    let value = serde_json::to_string(&body).unwrap();
+    tracing::debug!(response = %value, "dispatching");
+    handle
+        .dispatch(move |wv| wv.eval(&*format!("sendToElm({})", value)))
+        .ok();
+}
+
 pub struct AppBuilder<'a, Cont> {
     container: WebViewBuilderContainer,
     builder: WebViewBuilder<
@@ -137,6 +133,10 @@ pub struct AppBuilder<'a, Cont> {
         Cont,
     >,
     fullscreen: bool,
+    hot_reload: Option<HotReload>,
+    assets: Assets,
+    shell: Shell,
+    page: Option<fn(String) -> Cont>,
 }
 
 impl<'a, Cont> AppBuilder<'a, Cont>
@@ -149,11 +149,16 @@ where
             container: api_container.into(),
             builder: web_view::builder(),
             fullscreen: false,
+            hot_reload: None,
+            assets: Assets::default(),
+            shell: Shell::new(),
+            page: None,
         }
     }
 
     pub fn content(mut self, content: Content<Cont>) -> Self {
         self.builder = self.builder.content(content);
+        self.page = None;
         self
     }
 
@@ -173,6 +178,136 @@ where
 
         self
     }
+
+    /// Development mode: replaces the content with the one rendered from
+    /// disk, and reloads the page whenever a watched file changes.
+    pub fn hot_reload(mut self, hot_reload: HotReload) -> Self {
+        self.hot_reload = Some(hot_reload);
+
+        self
+    }
+
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.container.set_protocol(protocol);
+
+        self
+    }
+
+    pub fn batch(mut self, batch: Batch) -> Self {
+        self.container.set_batch(batch);
+
+        self
+    }
+
+    /// Answers the `realm/devtools/*` apis, keeping the last `history`
+    /// requests. Meant for development builds.
+    pub fn devtools(mut self, history: usize) -> Self {
+        self.container.enable_devtools(history);
+
+        self
+    }
+
+    /// Records every request and response to a JSON Lines file, to be
+    /// replayed later with `HeadlessApp::replay`.
+    pub fn record<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.container.set_recording(path.into());
+
+        self
+    }
+
+    pub fn events(&self) -> Addr<EventBus> {
+        self.container.events()
+    }
+
+    /// Runs `f` once the app starts, with the actors and services loaded.
+    pub fn on_start<F, Fut>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&ActorContainer) -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.container.lifecycle().on_start(f);
+
+        self
+    }
+
+    /// Runs `f` once the app stopped answering requests, before the
+    /// `closable` actors are closed.
+    pub fn on_close<F, Fut>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&ActorContainer) -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.container.lifecycle().on_close(f);
+
+        self
+    }
+
+    /// How long a shutdown waits for requests in flight and for closing
+    /// actors, 5 seconds by default.
+    pub fn shutdown_timeout(mut self, deadline: Duration) -> Self {
+        self.container.lifecycle().deadline(deadline);
+
+        self
+    }
+
+    /// Renders the shell with every asset added, once.
+    fn render_shell(&mut self) {
+        if let Some(page) = self.page.take() {
+            let html = self.shell.render_with(&self.assets);
+            self.builder.content = Some(Content::Html(page(html)));
+        }
+    }
+
+    pub fn headless(self) -> HeadlessBuilder {
+        HeadlessBuilder {
+            container: self.container,
+        }
+    }
+
+    #[cfg(feature = "http")]
+    pub fn http<A: std::net::ToSocketAddrs>(mut self, addr: A) -> HttpBuilder<A, Cont> {
+        self.render_shell();
+        let AppBuilder {
+            container,
+            mut builder,
+            fullscreen: _,
+            hot_reload,
+            assets,
+            shell: _,
+            page: _,
+        } = self;
+        HttpBuilder {
+            container,
+            content: builder.content.take(),
+            assets,
+            addr,
+            hot_reload: hot_reload.is_some(),
+        }
+    }
+}
+
+impl<'a, Cont> AppBuilder<'a, Cont>
+where
+    Cont: AsRef<str> + From<String>,
+{
+    /// Adds an asset embedded with `asset!`, and replaces the content with
+    /// the shell inlining the stylesheets and scripts, rendered once the
+    /// app is built. An http app also serves every asset at
+    /// `/assets/{name}`.
+    pub fn asset(mut self, name: &str, asset: Asset) -> Self {
+        self.assets.insert(name, asset);
+        self.page = Some(Cont::from);
+
+        self
+    }
+
+    /// Replaces the content with `shell`, rendered after the assets.
+    pub fn shell(mut self, shell: Shell) -> Self {
+        self.shell = shell;
+        self.page = Some(Cont::from);
+
+        self
+    }
 }
 
 impl<'a, Cont, API> Load<API> for AppBuilder<'a, Cont>
@@ -183,9 +318,25 @@ where
 
     fn load(mut self, api: API) -> Self::Result {
         let api_key = api.api_key();
-        let addr = api.start();
+        self.container.load_schema(api_key, api.schema());
+        let addr = Supervisor::start(move |_| api);
+
+        self.container.load_recipient(api_key, addr.clone());
+        self.container.load_canceller(api_key, addr);
+        self
+    }
+}
+
+impl<'a, Cont> Load<Router> for AppBuilder<'a, Cont> {
+    type Result = Self;
+
+    fn load(mut self, router: Router) -> Self::Result {
+        for route in router.prefixed() {
+            self.container.load_schema(&route.key, route.schema);
+            self.container.load_recipient(&route.key, route.recipient);
+            self.container.load_canceller(&route.key, route.canceller);
+        }
 
-        self.container.load_recipient(api_key, addr);
         self
     }
 }
@@ -205,25 +356,149 @@ where
     }
 }
 
+impl<'a, Cont, A> Load<WebViewClosableActor<A>> for AppBuilder<'a, Cont>
+where
+    A: Actor<Context = Context<A>> + Handler<Close>,
+{
+    type Result = Self;
+
+    fn load(mut self, closable: WebViewClosableActor<A>) -> Self::Result {
+        let addr = closable.0;
+
+        self.container
+            .lifecycle()
+            .closable(addr.clone().recipient());
+        self.container.load_actor(addr);
+
+        self
+    }
+}
+
+impl<'a, Cont, A> Load<WebViewMeteredActor<A>> for AppBuilder<'a, Cont>
+where
+    A: Actor<Context = Context<A>> + Handler<Collect>,
+{
+    type Result = Self;
+
+    fn load(mut self, metered: WebViewMeteredActor<A>) -> Self::Result {
+        let addr = metered.0;
+
+        self.container.load_metrics_source(addr.clone().recipient());
+        self.container.load_actor(addr);
+
+        self
+    }
+}
+
+impl<'a, Cont, T> Load<WebViewShared<T>> for AppBuilder<'a, Cont>
+where
+    T: Send + Sync + 'static,
+{
+    type Result = Self;
+
+    fn load(mut self, service: WebViewShared<T>) -> Self::Result {
+        self.container.load_shared(service.0);
+
+        self
+    }
+}
+
+impl<'a, Cont, Factory, Fut, Deps> Load<WebViewApiFactory<Factory, Deps>> for AppBuilder<'a, Cont>
+where
+    Factory: Fn(Deps) -> Fut,
+    Fut: Future + Send + 'a,
+    Fut::Output: KeyedActor,
+    Deps: FromContainer,
+    Cont: Send + 'a,
+{
+    type Result = Pin<Box<dyn Future<Output = Result<Self, BuildError>> + 'a>>;
+
+    fn load(self, arg: WebViewApiFactory<Factory, Deps>) -> Self::Result {
+        let ref factory = arg.0;
+        match Deps::extract(self.container.actor_container()) {
+            Ok(deps) => {
+                let api = factory(deps);
+                Box::pin(async move { Ok(self.load(api.await)) })
+            }
+            Err(missing) => Box::pin(ready(Err(BuildError::missing::<Fut::Output>(
+                arg.2, missing,
+            )))),
+        }
+    }
+}
+
+macro_rules! impl_extractor {
+    ($($name:ident),+) => {
+        #[allow(non_snake_case)]
+        impl<'a, Cont, Factory, Fut, $($name),+> Load<WebViewApiFactory<Factory, Args<($($name,)+)>>>
+            for AppBuilder<'a, Cont>
+        where
+            Factory: Fn($($name),+) -> Fut,
+            Fut: Future + Send + 'a,
+            Fut::Output: KeyedActor,
+            $($name: FromContainer,)+
+            Cont: Send + 'a,
+        {
+            type Result = Pin<Box<dyn Future<Output = Result<Self, BuildError>> + 'a>>;
+
+            fn load(self, arg: WebViewApiFactory<Factory, Args<($($name,)+)>>) -> Self::Result {
+                let ref factory = arg.0;
+                match <($($name,)+)>::extract(self.container.actor_container()) {
+                    Ok(($($name,)+)) => {
+                        let api = factory($($name),+);
+                        Box::pin(async move { Ok(self.load(api.await)) })
+                    }
+                    Err(missing) => {
+                        Box::pin(ready(Err(BuildError::missing::<Fut::Output>(arg.2, missing))))
+                    }
+                }
+            }
+        }
+    };
+}
+
+impl_extractor!(A, B);
+impl_extractor!(A, B, C);
+impl_extractor!(A, B, C, D);
+impl_extractor!(A, B, C, D, E);
+
+impl<'a, Cont, M> Load<WebViewMiddleware<M>> for AppBuilder<'a, Cont>
+where
+    M: Middleware,
+{
+    type Result = Self;
+
+    fn load(mut self, middleware: WebViewMiddleware<M>) -> Self::Result {
+        self.container.load_middleware(middleware.0);
+
+        self
+    }
+}
 
 impl<'a, Cont> Application for AppBuilder<'a, Cont>
 where
     Cont: AsRef<str>,
 {
-    type Result = WebView<'a, MaybeUninit<Addr<App>>>;
+    type Result = WebViewApp<'a>;
 
-    fn finish(self) -> Self::Result {
+    fn finish(mut self) -> Self::Result {
         fn message_handler(wv: &mut WebView<MaybeUninit<Addr<App>>>, msg: &str) -> WVResult {
             let data = wv.user_data();
             let data = unsafe { data.as_ptr().as_ref() }.unwrap();
             data.do_send(Received(msg.into()));
             Ok(())
         };
+        self.render_shell();
         let AppBuilder {
-            container,
+            mut container,
             builder,
             fullscreen,
+            hot_reload,
+            assets: _,
+            shell: _,
+            page: _,
         } = self;
+        let lifecycle = container.take_lifecycle();
         let mut webview = builder
             .user_data(MaybeUninit::uninit())
             .invoke_handler(message_handler)
@@ -232,17 +507,117 @@ where
 
         let handle: Handle<MaybeUninit<Addr<App>>> = webview.handle();
         let arc = Arc::new(WebViewBuilderContainer::finalize(container));
+        let arbiter = Arbiter::current();
 
+        let container = arc.clone();
         let addr = SyncArbiter::start(4, move || App {
             handle: handle.clone(),
             container: arc.clone(),
+            arbiter: arbiter.clone(),
         });
 
         *webview.user_data_mut() = MaybeUninit::new(addr);
 
         webview.set_fullscreen(fullscreen);
 
-        webview
+        WebViewApp {
+            webview,
+            container,
+            lifecycle,
+            hot_reload,
+        }
+    }
+}
+
+/// A built app, ready to `run`.
+pub struct WebViewApp<'a> {
+    webview: WebView<'a, MaybeUninit<Addr<App>>>,
+    container: Arc<WebViewContainer>,
+    lifecycle: Lifecycle,
+    hot_reload: Option<HotReload>,
+}
+
+impl<'a> WebViewApp<'a> {
+    pub fn webview(&mut self) -> &mut WebView<'a, MaybeUninit<Addr<App>>> {
+        &mut self.webview
+    }
+
+    /// Runs the `on_start` hooks, then the webview until it is closed,
+    /// yielding to the actors between its events and at least every
+    /// `TICK`. The app is then shut down gracefully.
+    pub async fn run(mut self) -> WVResult<ExitReport> {
+        let shared = self.container.shared();
+        self.lifecycle.start(&shared).await;
+        let _watching = self.hot_reload.take().map(|hot_reload| {
+            reload(&mut self.webview, hot_reload.render());
+            let handle = self.webview.handle();
+            hot_reload.spawn(move |content| {
+                handle
+                    .dispatch(move |webview| {
+                        reload(webview, content);
+                        Ok(())
+                    })
+                    .is_ok()
+            })
+        });
+        let handle = self.webview.handle();
+        let webview = &mut self.webview;
+        let wake = move || handle.dispatch(|_| Ok(())).is_ok();
+        let result = drive(|| webview.step(), wake, TICK).await;
+        let report = self
+            .lifecycle
+            .shutdown(&shared, self.container.tracker())
+            .await;
+        result.map(|_| report)
+    }
+}
+
+/// How often an idle webview is woken for the actors to answer.
+const TICK: Duration = Duration::from_millis(10);
+
+/// Steps an event loop until it closes. `step` blocks until the next
+/// event, so a thread calls `wake` every `tick` to queue one: the actors
+/// sharing the thread then get to answer even when no UI event comes.
+/// `wake` returns `false` once the loop is gone.
+pub(crate) async fn drive<S, W>(mut step: S, wake: W, tick: Duration) -> WVResult
+where
+    S: FnMut() -> Option<WVResult>,
+    W: Fn() -> bool + Send + 'static,
+{
+    let stopped = Arc::new(AtomicBool::new(false));
+    let _ticking = Ticking(stopped.clone());
+    thread::spawn(move || {
+        while !stopped.load(Ordering::SeqCst) && wake() {
+            thread::sleep(tick);
+        }
+    });
+    loop {
+        match step() {
+            Some(Ok(())) => tokio::task::yield_now().await,
+            Some(Err(err)) => return Err(err),
+            None => return Ok(()),
+        }
+    }
+}
+
+struct Ticking(Arc<AtomicBool>);
+
+impl Drop for Ticking {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+fn reload<T>(webview: &mut WebView<T>, content: std::io::Result<String>) {
+    let reloaded = match content {
+        Ok(html) => webview
+            .eval(&reload_script(&html))
+            .map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    match reloaded {
+        Ok(()) => tracing::info!("content reloaded"),
+        Err(err) => tracing::warn!(error = %err, "unable to reload the content"),
     }
 }
 
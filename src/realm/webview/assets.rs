@@ -0,0 +1,140 @@
+use std::path::Path;
+
+use super::shell::Shell;
+
+/// A static file embedded in the binary with `asset!`, so that the app
+/// renders without network access.
+#[derive(Clone, Copy, Debug)]
+pub struct Asset {
+    bytes: &'static [u8],
+    mime: &'static str,
+}
+
+/// Embeds the file at `path`, relative to the current file, as an `Asset`
+/// whose type is guessed from its extension.
+#[macro_export]
+macro_rules! asset {
+    ($path:literal) => {
+        $crate::webview::prelude::Asset::embed($path, include_bytes!($path))
+    };
+}
+
+fn mime(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("css") => "text/css",
+        Some("js") | Some("mjs") => "application/javascript",
+        Some("html") | Some("htm") => "text/html",
+        Some("json") | Some("map") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("eot") => "application/vnd.ms-fontobject",
+        _ => "application/octet-stream",
+    }
+}
+
+impl Asset {
+    pub fn embed(path: &str, bytes: &'static [u8]) -> Self {
+        Asset {
+            bytes,
+            mime: mime(path),
+        }
+    }
+
+    pub fn with_mime(bytes: &'static [u8], mime: &'static str) -> Self {
+        Asset { bytes, mime }
+    }
+
+    pub fn bytes(&self) -> &'static [u8] {
+        self.bytes
+    }
+
+    pub fn mime(&self) -> &'static str {
+        self.mime
+    }
+
+    pub fn text(&self) -> Option<&'static str> {
+        std::str::from_utf8(self.bytes).ok()
+    }
+
+    pub fn data_uri(&self) -> String {
+        format!("data:{};base64,{}", self.mime, base64::encode(self.bytes))
+    }
+
+    pub fn is_stylesheet(&self) -> bool {
+        self.mime == "text/css"
+    }
+
+    pub fn is_script(&self) -> bool {
+        self.mime == "application/javascript"
+    }
+}
+
+/// The assets of an app by name, in the order they were added: the
+/// stylesheets and scripts of a generated page follow it.
+#[derive(Clone, Default)]
+pub struct Assets(Vec<(String, Asset)>);
+
+impl Assets {
+    pub fn insert(&mut self, name: &str, asset: Asset) {
+        match self.0.iter_mut().find(|(other, _)| other == name) {
+            Some((_, other)) => *other = asset,
+            None => self.0.push((name.into(), asset)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Asset> {
+        self.0
+            .iter()
+            .find(|(other, _)| other == name)
+            .map(|(_, asset)| *asset)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Asset)> {
+        self.0.iter().map(|(name, asset)| (&**name, *asset))
+    }
+
+    /// The stylesheet, with the `url(...)`s naming other assets, like
+    /// fonts and images, replaced by data URIs.
+    pub fn inline_stylesheet(&self, asset: Asset) -> String {
+        let mut css = asset.text().unwrap_or_default().to_string();
+        let embedded = self
+            .iter()
+            .filter(|(_, other)| !other.is_stylesheet() && !other.is_script());
+        for (name, other) in embedded {
+            let patterns = [
+                format!("url({})", name),
+                format!("url(\"{}\")", name),
+                format!("url('{}')", name),
+            ];
+            if patterns.iter().any(|pattern| css.contains(pattern)) {
+                let inlined = format!("url(\"{}\")", other.data_uri());
+                for pattern in patterns.iter() {
+                    css = css.replace(pattern, &inlined);
+                }
+            }
+        }
+        css
+    }
+
+    /// A page with every stylesheet inlined in its head, and every script
+    /// inlined after the `elm` mount point.
+    pub fn page(&self) -> String {
+        Shell::new().render_with(self)
+    }
+}
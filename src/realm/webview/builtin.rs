@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+
+use actix::prelude::*;
+use futures::future::ready;
+use futures::FutureExt;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::api;
+
+use super::describe::ApiSchema;
+use super::router::RouteTable;
+use super::web_view_api;
+use super::Cancel;
+use super::InvokeRequest;
+use super::RequestId;
+
+pub(crate) const CANCEL: &str = "realm/cancel";
+pub(crate) const SUBSCRIBE: &str = "realm/subscribe";
+pub(crate) const UNSUBSCRIBE: &str = "realm/unsubscribe";
+pub(crate) const DESCRIBE: &str = "realm/describe";
+pub(crate) const STATUS: &str = "realm/status";
+pub(crate) const METRICS: &str = "realm/metrics";
+
+#[derive(Deserialize, JsonSchema)]
+pub struct CancelRequest {
+    api_name: String,
+    id: RequestId,
+}
+
+pub(crate) fn cancel(cancellers: RouteTable<Recipient<Cancel>>) -> Recipient<InvokeRequest> {
+    let handler = move |CancelRequest { api_name, id }: CancelRequest| {
+        let canceller = cancellers
+            .get(&*api_name)
+            .map(|(canceller, _)| canceller.clone());
+        async move {
+            match canceller {
+                Some(canceller) => canceller.send(Cancel(id)).await.unwrap_or(false),
+                None => false,
+            }
+        }
+        .boxed()
+    };
+    web_view_api(CANCEL, api(handler)).start().recipient()
+}
+
+pub(crate) fn describe(schemas: HashMap<String, ApiSchema>) -> Recipient<InvokeRequest> {
+    let schemas = serde_json::to_value(schemas).unwrap();
+    let handler = move |_: ()| ready(schemas.clone());
+    web_view_api(DESCRIBE, api(handler)).start().recipient()
+}
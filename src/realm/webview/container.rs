@@ -1,52 +1,373 @@
+use std::any::type_name;
 use std::any::Any;
 use std::any::TypeId;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Instant;
 
 use actix::fut::wrap_future;
 use actix::prelude::*;
+use futures::channel::mpsc;
+use futures::channel::mpsc::UnboundedSender;
+use futures::future::ready;
+use futures::future::BoxFuture;
+use futures::stream;
+use futures::stream::BoxStream;
+use futures::FutureExt;
+use futures::StreamExt;
+use serde_json::Value;
+use tracing::field;
+use tracing::Instrument;
+use tracing::Span;
 
+use super::api::mailbox_error;
+use super::builtin;
+use super::builtin::CancelRequest;
+use super::describe::ApiSchema;
+use super::devtools;
+use super::devtools::Attach;
+use super::devtools::DevTools;
+use super::devtools::Inspect;
+use super::errors::failure;
+use super::events::EventBus;
+use super::events::Subscription;
+use super::extract::Shared;
+use super::lifecycle::Lifecycle;
+use super::lifecycle::Tracker;
+use super::metrics::Collect;
+use super::metrics::MetricsBoard;
+use super::metrics::MetricsRequest;
+use super::metrics::MetricsSnapshot;
+use super::metrics::Observe;
+use super::metrics::Source;
+use super::middleware::Middleware;
+use super::middleware::MiddlewareChain;
+use super::middleware::Next;
+use super::protocol;
+use super::protocol::Batch;
+use super::protocol::Message;
+use super::protocol::Protocol;
+use super::record::Recording;
+use super::router::RouteParams;
+use super::router::RouteTable;
+use super::stream::ordered;
+use super::supervise::ApiStatus;
+use super::supervise::StatusBoard;
+use super::ApiResponse;
 use super::AppString;
+use super::Cancel;
+use super::ErrorKind;
+use super::InvokeBody;
 use super::InvokeRequest;
 
-pub type ActorApiContainer = HashMap<&'static str, Recipient<InvokeRequest>>;
+pub type ActorApiContainer = RouteTable<Recipient<InvokeRequest>>;
 
-pub struct ActorContainer(HashMap<TypeId, Arc<dyn Any + Send + Sync + 'static>>);
+pub struct ActorContainer {
+    items: HashMap<TypeId, Arc<dyn Any + Send + Sync + 'static>>,
+    names: HashMap<TypeId, &'static str>,
+}
 
 impl ActorContainer {
     pub fn load<T: Actor>(&mut self, addr: Addr<T>) {
-        self.0.insert(TypeId::of::<T>(), Arc::new(addr));
+        self.items.insert(TypeId::of::<T>(), Arc::new(addr));
+        self.names.insert(TypeId::of::<T>(), type_name::<T>());
     }
 
     pub fn get<T: Actor + Any + Send + Sync>(&self) -> Option<Addr<T>> {
-        self.0
+        self.items
             .get(&TypeId::of::<T>())
             .and_then(|b| Arc::clone(b).downcast().ok())
             .map(|arc| Addr::clone(&*arc))
             .clone()
     }
+
+    pub fn provide<T: Send + Sync + 'static>(&mut self, service: T) {
+        self.items
+            .insert(TypeId::of::<Shared<T>>(), Arc::new(service));
+        self.names
+            .insert(TypeId::of::<Shared<T>>(), type_name::<Shared<T>>());
+    }
+
+    pub fn get_shared<T: Send + Sync + 'static>(&self) -> Option<Shared<T>> {
+        self.items
+            .get(&TypeId::of::<Shared<T>>())
+            .and_then(|b| Arc::clone(b).downcast().ok())
+            .map(Shared)
+    }
+
+    /// Type names of the actors and services loaded, sorted.
+    pub fn type_names(&self) -> Vec<&'static str> {
+        let mut names = self.names.values().copied().collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
 }
 
 impl From<HashMap<TypeId, Arc<dyn Any + Send + Sync>>> for ActorContainer {
-    fn from(item: HashMap<TypeId, Arc<dyn Any + Send + Sync>>) -> Self {
-        ActorContainer(item)
+    fn from(items: HashMap<TypeId, Arc<dyn Any + Send + Sync>>) -> Self {
+        ActorContainer {
+            items,
+            names: HashMap::new(),
+        }
     }
 }
 
 pub(crate) struct WebViewBuilderContainer {
-    api_container: HashMap<&'static str, Recipient<InvokeRequest>>,
+    api_container: ActorApiContainer,
+    cancel_container: RouteTable<Recipient<Cancel>>,
+    schemas: HashMap<String, ApiSchema>,
     actor_container: ActorContainer,
+    middlewares: Vec<Box<dyn Middleware>>,
+    events: Addr<EventBus>,
+    protocol: Protocol,
+    batch: Batch,
+    lifecycle: Lifecycle,
+    recording: Option<PathBuf>,
     debug_content: Vec<AppString>,
 }
 
 pub(crate) struct WebViewContainer {
     actor_container: Arc<ActorContainer>,
     api_container: ActorApiContainer,
+    middlewares: MiddlewareChain,
+    protocol: Protocol,
+    batch: Batch,
+    tracker: Tracker,
+    metrics: Addr<MetricsBoard>,
+    recording: Option<Recording>,
+    devtools: Option<Addr<DevTools>>,
 }
 
 impl WebViewContainer {
-    pub fn get(&self, key: &str) -> Option<&Recipient<InvokeRequest>> {
-        self.api_container.get(key)
+    pub fn get(&self, key: &str) -> Option<(&str, &Recipient<InvokeRequest>, RouteParams)> {
+        self.api_container.resolve(key)
+    }
+
+    pub fn chain(&self, api: &Recipient<InvokeRequest>) -> Next {
+        Next::new(self.middlewares.clone(), api.clone())
+    }
+
+    pub fn route(
+        &self,
+        body: InvokeBody,
+        caller: Recipient<AppString>,
+        parts: UnboundedSender<ApiResponse>,
+    ) -> Result<(String, BoxFuture<'static, ApiResponse>), ApiResponse> {
+        match self.get(&*body.api_name) {
+            Some((key, api, params)) => {
+                let data = self.shared();
+                let next = self.chain(api);
+                let route = String::from(key);
+                let response = next.call(InvokeRequest {
+                    body,
+                    caller,
+                    data,
+                    params,
+                    parts,
+                    route: route.clone(),
+                });
+                Ok((route, response))
+            }
+            None => Err(failure(
+                "request/not-found".into(),
+                body.id.clone(),
+                ErrorKind::NotFound,
+                format!("Unable to find: {} api", body.api_name),
+            )),
+        }
+    }
+
+    pub fn encode(&self, response: &ApiResponse) -> Option<String> {
+        self.protocol
+            .encode(response)
+            .map(|response| response.to_string())
+    }
+
+    pub fn notification(&self, message: String) -> String {
+        if let Some(ref recording) = self.recording {
+            recording.notification(&message);
+        }
+        self.protocol.notification(message)
+    }
+
+    fn call(
+        &self,
+        value: Value,
+        caller: Recipient<AppString>,
+        span: &Span,
+    ) -> BoxStream<'static, ApiResponse> {
+        let started = Instant::now();
+        let (parts, received) = mpsc::unbounded();
+        let routed = span.in_scope(|| {
+            let body = self.protocol.parse(value)?;
+            span.record("api_name", &body.api_name.as_str());
+            if let Some(ref id) = body.id {
+                span.record("id", &field::display(id));
+            }
+            tracing::trace!("parsed");
+            let inspected = match self.devtools {
+                Some(ref devtools) if !devtools::is_devtool(&body.api_name) => {
+                    Some((devtools.clone(), body.clone()))
+                }
+                _ => None,
+            };
+            let seq = self
+                .recording
+                .as_ref()
+                .map(|recording| recording.request(&body));
+            let routed = self.route(body, caller, parts);
+            if routed.is_ok() {
+                tracing::trace!("routed");
+            }
+            Ok((inspected, seq, routed))
+        });
+        let (api_key, mut inspected, seq, response) = match routed {
+            Ok((inspected, seq, Ok((api_key, response)))) => {
+                self.metrics.do_send(Observe::Enter(api_key.clone()));
+                (Some(api_key), inspected, seq, response)
+            }
+            Ok((inspected, seq, Err(response))) => (None, inspected, seq, ready(response).boxed()),
+            Err(response) => (None, None, None, ready(response).boxed()),
+        };
+        let (answered, metrics) = (span.clone(), self.metrics.clone());
+        let recording = self.recording.clone();
+        let mut streamed = vec![];
+        ordered(received, response.instrument(span.clone()).boxed())
+            .map(move |response| {
+                let _answered = answered.enter();
+                let api_key = api_key
+                    .clone()
+                    .unwrap_or_else(|| response.api_name().into());
+                if response.is_partial() {
+                    if inspected.is_some() {
+                        streamed.push(serde_json::to_value(&response).unwrap_or_default());
+                    }
+                    metrics.do_send(Observe::Part(api_key));
+                    tracing::trace!("streamed");
+                    return response;
+                }
+                let latency = started.elapsed();
+                if let (Some(recording), Some(seq)) = (&recording, seq) {
+                    recording.response(seq, &response);
+                }
+                if let Some((devtools, body)) = inspected.take() {
+                    let inspected = serde_json::to_value(&response).unwrap_or_default();
+                    let streamed = std::mem::take(&mut streamed);
+                    devtools.do_send(Inspect::Exchange(body, streamed, inspected, latency));
+                }
+                metrics.do_send(Observe::Finish(api_key, latency, (&response).into()));
+                answered.record("latency_ms", &(latency.as_millis() as u64));
+                answered.record("outcome", &outcome(&response));
+                tracing::debug!("answered");
+                response
+            })
+            .boxed()
+    }
+
+    /// Parses and routes a message, yielding the encoded answers the
+    /// protocol expects: none for a notification, one for a request or a
+    /// combined batch, and one per request of an individual batch.
+    pub fn dispatch(
+        &self,
+        param: &str,
+        caller: Recipient<AppString>,
+    ) -> BoxStream<'static, String> {
+        if self.tracker.is_closing() {
+            let closing = failure(
+                "request/failed".into(),
+                None,
+                ErrorKind::Unreachable,
+                "App is shutting down",
+            );
+            return stream::iter(self.encode(&closing)).boxed();
+        }
+        let in_flight = self.tracker.enter();
+        self.answer(param, caller)
+            .map(move |response| {
+                let _in_flight = &in_flight;
+                response
+            })
+            .boxed()
+    }
+
+    fn answer(&self, param: &str, caller: Recipient<AppString>) -> BoxStream<'static, String> {
+        let protocol = self.protocol;
+        match protocol::read(param) {
+            Ok(Message::Single(value)) => {
+                let span = request_span();
+                self.call(value, caller, &span)
+                    .filter_map(move |response| {
+                        let _serializing = span.enter();
+                        let encoded = protocol.encode(&response).map(|it| it.to_string());
+                        if let Some(ref encoded) = encoded {
+                            tracing::trace!(bytes = encoded.len(), "serialized");
+                        }
+                        ready(encoded)
+                    })
+                    .boxed()
+            }
+            Ok(Message::Batch(values)) => {
+                let batch = tracing::debug_span!("batch", size = values.len());
+                let _batch = batch.enter();
+                let calls = values
+                    .into_iter()
+                    .map(|value| self.call(value, caller.clone(), &request_span()))
+                    .collect::<Vec<_>>();
+                match self.batch {
+                    Batch::Combined => {
+                        let mut answers = calls.iter().map(|_| None).collect::<Vec<_>>();
+                        let calls = calls
+                            .into_iter()
+                            .enumerate()
+                            .map(|(index, call)| call.map(move |response| (index, response)));
+                        stream::select_all(calls)
+                            .filter_map(move |(index, response)| {
+                                let encoded = match response.is_partial() {
+                                    true => protocol.encode(&response),
+                                    false => {
+                                        answers[index] = Some(response);
+                                        match answers.iter().all(Option::is_some) {
+                                            true => {
+                                                let answers = answers.drain(..).flatten();
+                                                protocol.encode_batch(&answers.collect::<Vec<_>>())
+                                            }
+                                            false => None,
+                                        }
+                                    }
+                                };
+                                ready(encoded.map(|it| it.to_string()))
+                            })
+                            .boxed()
+                    }
+                    Batch::Individual => stream::select_all(calls)
+                        .filter_map(move |response| {
+                            ready(protocol.encode(&response).map(|it| it.to_string()))
+                        })
+                        .boxed(),
+                }
+            }
+            Err(response) => stream::iter(self.encode(&response)).boxed(),
+        }
+    }
+}
+
+/// Span of one request, from parsing to the serialization of its answer.
+fn request_span() -> Span {
+    tracing::info_span!(
+        "request",
+        api_name = field::Empty,
+        id = field::Empty,
+        latency_ms = field::Empty,
+        outcome = field::Empty,
+    )
+}
+
+fn outcome(response: &ApiResponse) -> &'static str {
+    match response.kind() {
+        _ if !response.is_error() => "ok",
+        Some(kind) => kind.code(),
+        None => ErrorKind::Api.code(),
     }
 }
 
@@ -54,43 +375,184 @@ impl WebViewContainer {
     pub fn shared(&self) -> Arc<ActorContainer> {
         self.actor_container.clone()
     }
+
+    pub(crate) fn tracker(&self) -> &Tracker {
+        &self.tracker
+    }
 }
 
 impl WebViewBuilderContainer {
     pub fn new(capacity: usize) -> Self {
+        let events = EventBus::default().start();
+        let mut actor_container: ActorContainer = HashMap::new().into();
+        actor_container.load(events.clone());
+        actor_container.load(StatusBoard::default().start());
+        actor_container.load(MetricsBoard::default().start());
         WebViewBuilderContainer {
-            api_container: HashMap::with_capacity(capacity),
-            actor_container: HashMap::new().into(),
+            api_container: RouteTable::with_capacity(capacity),
+            cancel_container: RouteTable::with_capacity(capacity),
+            schemas: HashMap::with_capacity(capacity),
+            actor_container,
+            middlewares: vec![],
+            events,
+            protocol: Protocol::default(),
+            batch: Batch::default(),
+            lifecycle: Lifecycle::default(),
+            recording: None,
             debug_content: vec![],
         }
     }
 
-    pub fn load_recipient<R: Into<Recipient<InvokeRequest>>>(
-        &mut self,
-        key: &'static str,
-        recipient: R,
-    ) {
-        self.api_container.entry(key).or_insert(recipient.into());
+    pub fn load_recipient<R: Into<Recipient<InvokeRequest>>>(&mut self, key: &str, recipient: R) {
+        if !self.api_container.contains(key) {
+            self.api_container.insert(key, recipient.into());
+        }
     }
 
     pub fn load_actor<T: Actor>(&mut self, actor: Addr<T>) {
         self.actor_container.load(actor);
     }
 
+    pub fn load_shared<T: Send + Sync + 'static>(&mut self, service: T) {
+        self.actor_container.provide(service);
+    }
+
+    pub fn load_canceller<R: Into<Recipient<Cancel>>>(&mut self, key: &str, recipient: R) {
+        if !self.cancel_container.contains(key) {
+            self.cancel_container.insert(key, recipient.into());
+        }
+    }
+
+    pub fn load_schema(&mut self, key: &str, schema: ApiSchema) {
+        self.schemas.entry(key.into()).or_insert(schema);
+    }
+
+    pub fn load_metrics_source(&mut self, source: Recipient<Collect>) {
+        if let Some(metrics) = self.actor_container.get::<MetricsBoard>() {
+            metrics.do_send(Source(source));
+        }
+    }
+
+    pub fn load_middleware<M: Middleware>(&mut self, middleware: M) {
+        self.middlewares.push(Box::new(middleware));
+    }
+
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    pub fn set_batch(&mut self, batch: Batch) {
+        self.batch = batch;
+    }
+
+    pub fn enable_devtools(&mut self, history: usize) {
+        self.actor_container.load(DevTools::new(history).start());
+    }
+
+    pub fn set_recording(&mut self, path: PathBuf) {
+        self.recording = Some(path);
+    }
+
+    pub fn lifecycle(&mut self) -> &mut Lifecycle {
+        &mut self.lifecycle
+    }
+
+    pub fn take_lifecycle(&mut self) -> Lifecycle {
+        std::mem::take(&mut self.lifecycle)
+    }
+
     pub fn actor_container(&self) -> &ActorContainer {
         &self.actor_container
     }
 
+    pub fn events(&self) -> Addr<EventBus> {
+        self.events.clone()
+    }
+
     pub fn finalize(
         WebViewBuilderContainer {
             actor_container,
-            api_container,
+            mut api_container,
+            cancel_container,
+            mut schemas,
+            middlewares,
+            events,
+            protocol,
+            batch,
+            lifecycle: _,
+            recording,
             debug_content: _,
         }: Self,
     ) -> WebViewContainer {
+        let recording = recording.and_then(|path| match Recording::create(&path) {
+            Ok(recording) => Some(recording),
+            Err(err) => {
+                tracing::warn!(error = %err, path = %path.display(), "unable to record");
+                None
+            }
+        });
+        api_container.insert(builtin::CANCEL, builtin::cancel(cancel_container));
+        api_container.insert(builtin::SUBSCRIBE, events.clone().recipient());
+        api_container.insert(builtin::UNSUBSCRIBE, events.recipient());
+        if let Some(status) = actor_container.get::<StatusBoard>() {
+            api_container.insert(builtin::STATUS, status.recipient());
+        }
+        let metrics = actor_container.get::<MetricsBoard>().unwrap();
+        api_container.insert(builtin::METRICS, metrics.clone().recipient());
+        schemas.insert(
+            builtin::CANCEL.into(),
+            ApiSchema::of::<CancelRequest, bool>(),
+        );
+        schemas.insert(
+            builtin::SUBSCRIBE.into(),
+            ApiSchema::of::<Subscription, String>(),
+        );
+        schemas.insert(
+            builtin::UNSUBSCRIBE.into(),
+            ApiSchema::of::<Subscription, String>(),
+        );
+        schemas.insert(
+            builtin::STATUS.into(),
+            ApiSchema::of::<(), HashMap<String, ApiStatus>>(),
+        );
+        schemas.insert(
+            builtin::METRICS.into(),
+            ApiSchema::of::<Option<MetricsRequest>, MetricsSnapshot>(),
+        );
+        let devtools = actor_container.get::<DevTools>();
+        if let Some(ref devtools) = devtools {
+            api_container.insert(
+                &format!("{}{{tool}}", devtools::PREFIX),
+                devtools.clone().recipient(),
+            );
+            for tool in devtools::TOOLS.iter() {
+                schemas.insert(
+                    format!("{}{}", devtools::PREFIX, tool),
+                    devtools::schema(tool),
+                );
+            }
+        }
+        schemas.insert(builtin::DESCRIBE.into(), ApiSchema::of::<(), Value>());
+        api_container.insert(builtin::DESCRIBE, builtin::describe(schemas));
+        let actor_container = Arc::new(actor_container);
+        let middlewares: MiddlewareChain = middlewares.into();
+        if let Some(ref devtools) = devtools {
+            devtools.do_send(Attach {
+                apis: api_container.clone(),
+                middlewares: middlewares.clone(),
+                container: actor_container.clone(),
+            });
+        }
         WebViewContainer {
-            actor_container: Arc::new(actor_container),
+            actor_container,
             api_container,
+            middlewares,
+            protocol,
+            batch,
+            tracker: Tracker::default(),
+            metrics,
+            recording,
+            devtools,
         }
     }
 }
@@ -103,23 +565,38 @@ impl Handler<AppString> for WebViewBuilderContainer {
     type Result = ();
 
     fn handle(&mut self, msg: AppString, _: &mut Self::Context) -> Self::Result {
-        println!("{}", msg.0);
+        tracing::debug!(message = %msg.0, "notification before the app started");
         self.debug_content.push(msg);
     }
 }
 
 impl Handler<InvokeRequest> for WebViewBuilderContainer {
-    type Result = ();
+    type Result = ResponseActFuture<Self, ApiResponse>;
 
-    fn handle(&mut self, msg: InvokeRequest, ctx: &mut Self::Context) -> Self::Result {
-        let api_name = msg.body().api_name();
-        self.api_container.get(&*api_name.clone()).and_then(
-            move |api: &Recipient<InvokeRequest>| {
-                wrap_future::<_, Self>(api.send(msg))
-                    .then(|_, _, _| async {}.actfuture())
-                    .spawn(ctx);
-                Some(())
-            },
-        );
+    fn handle(&mut self, msg: InvokeRequest, _: &mut Self::Context) -> Self::Result {
+        let api_name = msg.body().api_name().clone();
+        let id = msg.body().id().cloned();
+        match self.api_container.resolve(&*api_name) {
+            Some((route, api, params)) => {
+                let route = route.into();
+                let msg = InvokeRequest {
+                    params,
+                    route,
+                    ..msg
+                };
+                Box::pin(wrap_future::<_, Self>(api.send(msg)).map(move |res, _, _| {
+                    res.unwrap_or_else(|err| mailbox_error(api_name, id, err))
+                }))
+            }
+            None => {
+                let result = failure(
+                    "request/not-found".into(),
+                    id,
+                    ErrorKind::NotFound,
+                    format!("Unable to find: {} api", api_name),
+                );
+                Box::pin(async move { result }.into_actor(self))
+            }
+        }
     }
 }
@@ -0,0 +1,155 @@
+use std::any::Any;
+use std::future::ready;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use actix::prelude::*;
+use futures::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::api::malformed_payload;
+use super::api::WebViewApi;
+use super::container::ActorContainer;
+use super::error;
+use super::errors::ApiOutput;
+use super::router::RouteParams;
+use super::success;
+use super::supervise::defer_panic;
+use super::ApiResponse;
+use super::AppString;
+use super::InvokeBody;
+use super::InvokeRequest;
+use super::RequestId;
+
+/// Whoever sent a request. Two requests from the same webview, headless
+/// app or browser session have equal callers.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Caller(pub(crate) Recipient<AppString>);
+
+impl Caller {
+    /// Pushes a message to the caller outside of any response.
+    pub fn notify<Data: Serialize>(&self, data: &Data) {
+        let send = serde_json::to_string(data).unwrap();
+        self.0.do_send(send.into()).ok();
+    }
+}
+
+/// Metadata of the request being handled by a `ContextApi`.
+#[derive(Clone)]
+pub struct RequestContext {
+    api_name: String,
+    id: Option<RequestId>,
+    container: Arc<ActorContainer>,
+    caller: Caller,
+    params: RouteParams,
+}
+
+impl RequestContext {
+    pub fn api_name(&self) -> &str {
+        &self.api_name
+    }
+
+    pub fn id(&self) -> Option<&RequestId> {
+        self.id.as_ref()
+    }
+
+    pub fn container(&self) -> &ActorContainer {
+        &self.container
+    }
+
+    pub fn actor<T: Actor + Any + Send + Sync>(&self) -> Option<Addr<T>> {
+        self.container.get()
+    }
+
+    pub fn caller(&self) -> &Caller {
+        &self.caller
+    }
+
+    /// A path parameter matched by the api's route pattern.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name)
+    }
+}
+
+/// An `Api` that also receives the `RequestContext` of every call.
+pub trait ContextApi {
+    type Input;
+    type Output;
+
+    fn handle(&self, msg: Self::Input, context: RequestContext) -> Self::Output;
+}
+
+pub struct ContextFn<F, T, O>(F, PhantomData<fn(T) -> O>);
+
+impl<F, T, O> ContextApi for ContextFn<F, T, O>
+where
+    F: Fn(T, RequestContext) -> O,
+{
+    type Input = T;
+    type Output = O;
+
+    fn handle(&self, msg: Self::Input, context: RequestContext) -> Self::Output {
+        self.0(msg, context)
+    }
+}
+
+/// Marks a `ContextApi`, to be loaded with `web_view_api`.
+pub struct Contextual<API>(pub(crate) API);
+
+impl<API> Handler<InvokeRequest> for WebViewApi<Contextual<API>>
+where
+    API: ContextApi + Unpin + 'static,
+    API::Input: DeserializeOwned,
+    API::Output: Future + Send,
+    <API::Output as Future>::Output: ApiOutput,
+{
+    type Result = ResponseActFuture<Self, ApiResponse>;
+
+    fn handle(&mut self, msg: InvokeRequest, _: &mut Self::Context) -> Self::Result {
+        let InvokeRequest {
+            body:
+                InvokeBody {
+                    id,
+                    api_name,
+                    payload,
+                },
+            caller,
+            data,
+            params,
+            route,
+            ..
+        } = msg;
+        let (name, request_id) = (api_name.clone(), id.clone());
+        let context = RequestContext {
+            api_name: api_name.clone(),
+            id: id.clone(),
+            container: data.clone(),
+            caller: Caller(caller),
+            params,
+        };
+        let result = serde_json::from_value(payload.clone());
+        let api = &self.api.0;
+        let handler = match result {
+            Ok(result) => defer_panic(|| api.handle(result, context))
+                .map(move |output| match output.into_result() {
+                    Ok(payload) => success(api_name, id, &payload),
+                    Err(err) => error(api_name, id, &err),
+                })
+                .right_future(),
+            Err(e) => ready(malformed_payload(api_name, id, &payload, e)).left_future(),
+        };
+        self.track(name, route, request_id, &data, handler)
+    }
+}
+
+pub fn contextual<F, T, O>(f: F) -> Contextual<ContextFn<F, T, O>>
+where
+    F: Fn(T, RequestContext) -> O,
+{
+    Contextual(ContextFn(f, PhantomData))
+}
+
+pub fn with_context<API: ContextApi>(api: API) -> Contextual<API> {
+    Contextual(api)
+}
@@ -0,0 +1,75 @@
+use futures::Future;
+use futures::Stream;
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::Api;
+
+use super::context::ContextApi;
+use super::context::Contextual;
+use super::errors::ApiOutput;
+use super::stream::Partial;
+use super::stream::Streaming;
+
+/// JSON Schemas of the payload an api expects and of the payload it
+/// answers with, as listed by `realm/describe`.
+#[derive(Serialize, Clone)]
+pub struct ApiSchema {
+    pub input: RootSchema,
+    pub output: RootSchema,
+}
+
+impl ApiSchema {
+    pub fn of<I: JsonSchema, O: JsonSchema>() -> Self {
+        ApiSchema {
+            input: schema_for!(I),
+            output: schema_for!(O),
+        }
+    }
+}
+
+pub trait Describe {
+    fn describe() -> ApiSchema;
+}
+
+impl<API> Describe for API
+where
+    API: Api,
+    API::Input: JsonSchema,
+    API::Output: Future,
+    <API::Output as Future>::Output: ApiOutput,
+    <<API::Output as Future>::Output as ApiOutput>::Payload: JsonSchema,
+{
+    fn describe() -> ApiSchema {
+        ApiSchema::of::<API::Input, <<API::Output as Future>::Output as ApiOutput>::Payload>()
+    }
+}
+
+/// A stream is described by the chunks it sends.
+impl<API, T> Describe for Streaming<API>
+where
+    API: Api,
+    API::Input: JsonSchema,
+    API::Output: Stream<Item = Partial<T>>,
+    T: ApiOutput,
+    T::Payload: JsonSchema,
+{
+    fn describe() -> ApiSchema {
+        ApiSchema::of::<API::Input, T::Payload>()
+    }
+}
+
+impl<API> Describe for Contextual<API>
+where
+    API: ContextApi,
+    API::Input: JsonSchema,
+    API::Output: Future,
+    <API::Output as Future>::Output: ApiOutput,
+    <<API::Output as Future>::Output as ApiOutput>::Payload: JsonSchema,
+{
+    fn describe() -> ApiSchema {
+        ApiSchema::of::<API::Input, <<API::Output as Future>::Output as ApiOutput>::Payload>()
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::iter;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use actix::prelude::*;
+use futures::channel::mpsc;
+use futures::future::ready;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::api::malformed_payload;
+use super::container::ActorApiContainer;
+use super::container::ActorContainer;
+use super::describe::ApiSchema;
+use super::errors::failure;
+use super::middleware::MiddlewareChain;
+use super::middleware::Next;
+use super::record::timestamp;
+use super::success;
+use super::ApiResponse;
+use super::ErrorKind;
+use super::InvokeBody;
+use super::InvokeRequest;
+use super::RequestId;
+
+pub(crate) const PREFIX: &str = "realm/devtools/";
+pub(crate) const TOOLS: [&str; 5] = ["apis", "actors", "requests", "lazy", "refire"];
+
+/// A request and its response, as kept by the inspector.
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct Exchange {
+    seq: u64,
+    /// Milliseconds since the Unix epoch, when the request was answered.
+    timestamp: u64,
+    api_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<RequestId>,
+    payload: Value,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    parts: Vec<Value>,
+    response: Value,
+    latency_ms: f64,
+}
+
+/// Initialization state of a `lazy` api, once it was first called.
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum LazyState {
+    Initializing,
+    Ready {
+        generation: u64,
+    },
+    Failed {
+        error: Value,
+    },
+    /// Dropped after a panic, built again by the next request.
+    Dropped,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) enum Inspect {
+    Exchange(InvokeBody, Vec<Value>, Value, Duration),
+    Lazy(&'static str, LazyState),
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct Attach {
+    pub(crate) apis: ActorApiContainer,
+    pub(crate) middlewares: MiddlewareChain,
+    pub(crate) container: Arc<ActorContainer>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct Refire {
+    seq: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct Refired {
+    latency_ms: f64,
+    response: Value,
+}
+
+/// Answers the `realm/devtools/*` apis of an app built with `devtools`:
+/// `apis`, `actors`, `requests`, `lazy` and `refire`.
+pub struct DevTools {
+    capacity: usize,
+    seq: u64,
+    history: VecDeque<Exchange>,
+    lazy: BTreeMap<&'static str, LazyState>,
+    attached: Option<Attach>,
+}
+
+impl DevTools {
+    pub(crate) fn new(capacity: usize) -> Self {
+        DevTools {
+            capacity,
+            seq: 0,
+            history: VecDeque::with_capacity(capacity),
+            lazy: BTreeMap::new(),
+            attached: None,
+        }
+    }
+
+    fn push(&mut self, body: InvokeBody, parts: Vec<Value>, response: Value, latency: Duration) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.history.len() == self.capacity {
+            self.history.pop_front();
+        }
+        self.seq += 1;
+        self.history.push_back(Exchange {
+            seq: self.seq,
+            timestamp: timestamp(),
+            api_name: body.api_name,
+            id: body.id,
+            payload: body.payload,
+            parts,
+            response,
+            latency_ms: latency.as_secs_f64() * 1000.0,
+        });
+    }
+
+    fn refire(&self, msg: InvokeRequest) -> ResponseActFuture<Self, ApiResponse> {
+        let InvokeRequest { body, caller, .. } = msg;
+        let InvokeBody {
+            api_name,
+            id,
+            payload,
+        } = body;
+        let (parts, mut streamed) = mpsc::unbounded();
+        let request = serde_json::from_value::<Refire>(payload.clone())
+            .map_err(|e| malformed_payload(api_name.clone(), id.clone(), &payload, e))
+            .and_then(|Refire { seq }| {
+                let exchange = self.history.iter().find(|exchange| exchange.seq == seq);
+                exchange.ok_or_else(|| {
+                    let message = format!("No request {} in the history", seq);
+                    failure(api_name.clone(), id.clone(), ErrorKind::NotFound, message)
+                })
+            })
+            .and_then(|exchange| {
+                let Attach {
+                    apis,
+                    middlewares,
+                    container,
+                } = self.attached.as_ref().unwrap();
+                let routed = apis.resolve(&exchange.api_name).ok_or_else(|| {
+                    let message = format!("Unable to find: {} api", exchange.api_name);
+                    failure(api_name.clone(), id.clone(), ErrorKind::NotFound, message)
+                })?;
+                let (route, api, params) = routed;
+                let body = InvokeBody {
+                    id: exchange.id.clone(),
+                    api_name: exchange.api_name.clone(),
+                    payload: exchange.payload.clone(),
+                };
+                let request = InvokeRequest {
+                    body: body.clone(),
+                    caller,
+                    data: container.clone(),
+                    params,
+                    parts,
+                    route: route.into(),
+                };
+                Ok((
+                    body,
+                    Next::new(middlewares.clone(), api.clone()).call(request),
+                ))
+            });
+        let (refired, response) = match request {
+            Ok(request) => request,
+            Err(response) => return Box::pin(ready(response).into_actor(self)),
+        };
+        let started = Instant::now();
+        Box::pin(response.into_actor(self).map(move |response, actor, _| {
+            let latency = started.elapsed();
+            let parts = iter::from_fn(|| streamed.try_next().ok().flatten())
+                .map(|part| serde_json::to_value(&part).unwrap_or_default())
+                .collect();
+            let response = serde_json::to_value(&response).unwrap_or_default();
+            let answer = Refired {
+                latency_ms: latency.as_secs_f64() * 1000.0,
+                response: response.clone(),
+            };
+            actor.push(refired, parts, response, latency);
+            success(api_name, id, &answer)
+        }))
+    }
+}
+
+impl Actor for DevTools {
+    type Context = Context<Self>;
+}
+
+impl Handler<Inspect> for DevTools {
+    type Result = ();
+
+    fn handle(&mut self, msg: Inspect, _: &mut Self::Context) -> Self::Result {
+        match msg {
+            Inspect::Exchange(body, parts, response, latency) => {
+                self.push(body, parts, response, latency)
+            }
+            Inspect::Lazy(api_key, state) => {
+                self.lazy.insert(api_key, state);
+            }
+        }
+    }
+}
+
+impl Handler<Attach> for DevTools {
+    type Result = ();
+
+    fn handle(&mut self, msg: Attach, _: &mut Self::Context) -> Self::Result {
+        self.attached = Some(msg);
+    }
+}
+
+impl Handler<InvokeRequest> for DevTools {
+    type Result = ResponseActFuture<Self, ApiResponse>;
+
+    fn handle(&mut self, msg: InvokeRequest, _: &mut Self::Context) -> Self::Result {
+        let (api_name, id) = (msg.body.api_name.clone(), msg.body.id.clone());
+        let attached = self.attached.as_ref();
+        let response = match (msg.params.get("tool"), attached) {
+            (_, None) => failure(
+                api_name,
+                id,
+                ErrorKind::Unreachable,
+                "Devtools not attached",
+            ),
+            (Some("apis"), Some(attached)) => {
+                let mut keys = attached.apis.keys().collect::<Vec<_>>();
+                keys.sort_unstable();
+                success(api_name, id, &keys)
+            }
+            (Some("actors"), Some(attached)) => {
+                success(api_name, id, &attached.container.type_names())
+            }
+            (Some("requests"), _) => success(api_name, id, &self.history),
+            (Some("lazy"), _) => success(api_name, id, &self.lazy),
+            (Some("refire"), _) => return self.refire(msg),
+            (tool, _) => {
+                let message = format!("Unknown devtool: {}", tool.unwrap_or_default());
+                failure(api_name, id, ErrorKind::NotFound, message)
+            }
+        };
+        Box::pin(ready(response).into_actor(self))
+    }
+}
+
+pub(crate) fn schema(tool: &str) -> ApiSchema {
+    match tool {
+        "apis" | "actors" => ApiSchema::of::<(), Vec<String>>(),
+        "requests" => ApiSchema::of::<(), Vec<Exchange>>(),
+        "lazy" => ApiSchema::of::<(), BTreeMap<String, LazyState>>(),
+        _ => ApiSchema::of::<Refire, Refired>(),
+    }
+}
+
+/// Reports a lazy api's state to the inspector, when one is loaded.
+pub(crate) fn report_lazy(
+    devtools: &Option<Addr<DevTools>>,
+    api_key: &'static str,
+    state: LazyState,
+) {
+    if let Some(devtools) = devtools {
+        devtools.do_send(Inspect::Lazy(api_key, state));
+    }
+}
+
+pub(crate) fn is_devtool(api_name: &str) -> bool {
+    api_name.starts_with(PREFIX)
+}
@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::hash::Hash;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::error;
+use super::ApiResponse;
+use super::ErrorKind;
+use super::RequestId;
+
+/// Error answered by an api. The client branches on `code`, shows
+/// `message`, and may send the request again when `retryable` is set.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ApiError {
+    code: String,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    details: Option<Value>,
+    #[serde(default)]
+    retryable: bool,
+}
+
+impl ApiError {
+    pub fn new<C: Into<String>, M: Into<String>>(code: C, message: M) -> Self {
+        ApiError {
+            code: code.into(),
+            message: message.into(),
+            details: None,
+            retryable: false,
+        }
+    }
+
+    pub fn details<D: Serialize>(mut self, details: &D) -> Self {
+        self.details = serde_json::to_value(details).ok();
+        self
+    }
+
+    pub fn retryable(mut self, retryable: bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.retryable
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<&str> for ApiError {
+    fn from(message: &str) -> Self {
+        ErrorKind::Api.error(message)
+    }
+}
+
+impl From<String> for ApiError {
+    fn from(message: String) -> Self {
+        ErrorKind::Api.error(message)
+    }
+}
+
+/// What an api resolves to, classified as a success or an error by its
+/// type: a `Result` fails with its `Err` turned into an `ApiError`, an
+/// `ApiPayload` succeeds.
+pub trait ApiOutput {
+    /// Type of the payload answered on success, listed by `realm/describe`.
+    type Payload;
+
+    fn into_result(self) -> Result<Value, ApiError>;
+}
+
+/// Marks a type an api answers with as is, e.g. `impl ApiPayload for Row {}`.
+pub trait ApiPayload: Serialize {}
+
+fn to_payload<T: Serialize>(output: &T) -> Result<Value, ApiError> {
+    serde_json::to_value(output).map_err(|err| ApiError::new("invalid_output", err.to_string()))
+}
+
+impl<T: ApiPayload> ApiOutput for T {
+    type Payload = T;
+
+    fn into_result(self) -> Result<Value, ApiError> {
+        to_payload(&self)
+    }
+}
+
+impl<T, E> ApiOutput for Result<T, E>
+where
+    T: Serialize,
+    E: Into<ApiError>,
+{
+    type Payload = T;
+
+    fn into_result(self) -> Result<Value, ApiError> {
+        self.map_err(Into::into)
+            .and_then(|output| to_payload(&output))
+    }
+}
+
+macro_rules! api_payload {
+    ($($ty:ty),+ $(,)?) => {
+        $(impl ApiPayload for $ty {})+
+    };
+}
+
+api_payload!(
+    (),
+    bool,
+    char,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    f32,
+    f64,
+    &'static str,
+    String,
+    Value,
+);
+
+impl<T: Serialize> ApiPayload for Option<T> {}
+
+impl<T: Serialize> ApiPayload for Vec<T> {}
+
+impl<K: Serialize + Eq + Hash, V: Serialize> ApiPayload for HashMap<K, V> {}
+
+impl<K: Serialize + Ord, V: Serialize> ApiPayload for BTreeMap<K, V> {}
+
+impl ErrorKind {
+    pub(crate) fn code(self) -> &'static str {
+        match self {
+            ErrorKind::Parse => "parse_error",
+            ErrorKind::Request => "invalid_request",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::Payload => "invalid_payload",
+            ErrorKind::Unreachable => "unreachable",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::Panic => "panic",
+            ErrorKind::Api => "api_error",
+        }
+    }
+
+    /// Error reported when a request fails before or around its api.
+    pub(crate) fn error<M: Into<String>>(self, message: M) -> ApiError {
+        let retryable = matches!(self, ErrorKind::Unreachable | ErrorKind::Timeout);
+        ApiError::new(self.code(), message).retryable(retryable)
+    }
+}
+
+pub(crate) fn failure<M: Into<String>>(
+    api_name: String,
+    id: Option<RequestId>,
+    kind: ErrorKind,
+    message: M,
+) -> ApiResponse {
+    error(api_name, id, &kind.error(message)).with_kind(kind)
+}
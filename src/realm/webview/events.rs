@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use actix::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::builtin::SUBSCRIBE;
+use super::errors::failure;
+use super::success;
+use super::AppString;
+use super::ErrorKind;
+use super::InvokeRequest;
+
+/// Envelope of every message pushed to the client without a request,
+/// told apart from an `ApiResponse` by its `topic` field.
+#[derive(Serialize, Clone)]
+pub struct EventBody {
+    topic: String,
+    payload: Value,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Publish(EventBody);
+
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub(crate) struct Subscribers(pub(crate) String);
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct Subscription {
+    topic: String,
+}
+
+/// Routes published events to the callers subscribed to their topic.
+/// It is loaded in the `ActorContainer`, so any api or actor can reach it.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: HashMap<String, HashSet<Recipient<AppString>>>,
+}
+
+impl Actor for EventBus {
+    type Context = Context<Self>;
+}
+
+impl Handler<Publish> for EventBus {
+    type Result = ();
+
+    fn handle(&mut self, Publish(event): Publish, _: &mut Self::Context) -> Self::Result {
+        if let Some(subscribers) = self.subscribers.get_mut(&event.topic) {
+            let send = serde_json::to_string(&event).unwrap();
+            subscribers.retain(|caller| {
+                !matches!(
+                    caller.do_send(send.clone().into()),
+                    Err(SendError::Closed(_))
+                )
+            });
+        }
+    }
+}
+
+impl Handler<Subscribers> for EventBus {
+    type Result = usize;
+
+    fn handle(&mut self, Subscribers(topic): Subscribers, _: &mut Self::Context) -> Self::Result {
+        self.subscribers.get(&topic).map_or(0, HashSet::len)
+    }
+}
+
+impl Handler<InvokeRequest> for EventBus {
+    type Result = MessageResult<InvokeRequest>;
+
+    fn handle(&mut self, msg: InvokeRequest, _: &mut Self::Context) -> Self::Result {
+        let InvokeRequest { body, caller, .. } = msg;
+        let subscription = serde_json::from_value::<Subscription>(body.payload);
+        MessageResult(match subscription {
+            Ok(Subscription { topic }) if body.api_name == SUBSCRIBE => {
+                self.subscribers
+                    .entry(topic.clone())
+                    .or_default()
+                    .insert(caller);
+                success(body.api_name, body.id, &topic)
+            }
+            Ok(Subscription { topic }) => {
+                if let Some(subscribers) = self.subscribers.get_mut(&topic) {
+                    subscribers.remove(&caller);
+                }
+                success(body.api_name, body.id, &topic)
+            }
+            Err(e) => failure(body.api_name, body.id, ErrorKind::Payload, e.to_string()),
+        })
+    }
+}
+
+pub fn publish<Data: Serialize>(topic: &str, data: &Data) -> Publish {
+    Publish(EventBody {
+        topic: topic.into(),
+        payload: serde_json::to_value(data).unwrap(),
+    })
+}
@@ -0,0 +1,144 @@
+use std::any::type_name;
+use std::any::Any;
+use std::fmt::Display;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use actix::prelude::*;
+
+use super::container::ActorContainer;
+
+/// A dependency taken from the `ActorContainer` by `api_init_with`. On
+/// failure it lists the type of every dependency that was not loaded.
+/// Tuples of up to 12 dependencies are dependencies too.
+pub trait FromContainer: Sized {
+    fn extract(container: &ActorContainer) -> Result<Self, Vec<&'static str>>;
+}
+
+impl<T> FromContainer for Addr<T>
+where
+    T: Actor + Any + Send + Sync,
+{
+    fn extract(container: &ActorContainer) -> Result<Self, Vec<&'static str>> {
+        container
+            .get::<T>()
+            .ok_or_else(|| vec![type_name::<Self>()])
+    }
+}
+
+/// A service that is not an actor, loaded with `shared(...)`.
+pub struct Shared<T>(pub(crate) Arc<T>);
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Shared(self.0.clone())
+    }
+}
+
+impl<T> Deref for Shared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> FromContainer for Shared<T>
+where
+    T: Send + Sync + 'static,
+{
+    fn extract(container: &ActorContainer) -> Result<Self, Vec<&'static str>> {
+        container
+            .get_shared::<T>()
+            .ok_or_else(|| vec![type_name::<Self>()])
+    }
+}
+
+/// An optional dependency, `None` when it was not loaded.
+impl<T: FromContainer> FromContainer for Option<T> {
+    fn extract(container: &ActorContainer) -> Result<Self, Vec<&'static str>> {
+        Ok(T::extract(container).ok())
+    }
+}
+
+impl FromContainer for () {
+    fn extract(_: &ActorContainer) -> Result<Self, Vec<&'static str>> {
+        Ok(())
+    }
+}
+
+macro_rules! impl_from_container {
+    ($($name:ident),+) => {
+        #[allow(non_snake_case)]
+        impl<$($name: FromContainer),+> FromContainer for ($($name,)+) {
+            fn extract(container: &ActorContainer) -> Result<Self, Vec<&'static str>> {
+                match ($($name::extract(container),)+) {
+                    ($(Ok($name),)+) => Ok(($($name,)+)),
+                    ($($name,)+) => {
+                        let mut missing = vec![];
+                        $(
+                            if let Err(names) = $name {
+                                missing.extend(names);
+                            }
+                        )+
+                        Err(missing)
+                    }
+                }
+            }
+        }
+    };
+}
+
+impl_from_container!(A);
+impl_from_container!(A, B);
+impl_from_container!(A, B, C);
+impl_from_container!(A, B, C, D);
+impl_from_container!(A, B, C, D, E);
+impl_from_container!(A, B, C, D, E, F);
+impl_from_container!(A, B, C, D, E, F, G);
+impl_from_container!(A, B, C, D, E, F, G, H);
+impl_from_container!(A, B, C, D, E, F, G, H, I);
+impl_from_container!(A, B, C, D, E, F, G, H, I, J);
+impl_from_container!(A, B, C, D, E, F, G, H, I, J, K);
+impl_from_container!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+/// Returned by `AppBuilder` when an api's dependencies were not loaded.
+#[derive(Debug)]
+pub struct BuildError {
+    api: &'static str,
+    missing: Vec<&'static str>,
+}
+
+impl BuildError {
+    /// Names the api by its key when the factory was given one, by its
+    /// type otherwise.
+    pub(crate) fn missing<API>(api_key: Option<&'static str>, missing: Vec<&'static str>) -> Self {
+        BuildError {
+            api: api_key.unwrap_or_else(type_name::<API>),
+            missing,
+        }
+    }
+
+    pub fn api(&self) -> &'static str {
+        self.api
+    }
+
+    pub fn missing_types(&self) -> &[&'static str] {
+        &self.missing
+    }
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Unable to initialize {}, missing: {}. Load them with `actor(...)` or `shared(...)` before the api",
+            self.api,
+            self.missing.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+pub struct WebViewShared<T>(pub(crate) T);
@@ -0,0 +1,123 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use actix::prelude::*;
+use futures::channel::mpsc;
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::channel::mpsc::UnboundedSender;
+use futures::StreamExt;
+
+use crate::Application;
+
+use super::app::Received;
+use super::container::WebViewBuilderContainer;
+use super::container::WebViewContainer;
+use super::lifecycle::ExitReport;
+use super::lifecycle::Lifecycle;
+use super::record;
+use super::record::read_recording;
+use super::record::ReplayReport;
+use super::AppString;
+
+/// Dispatcher of an app running without a webview. It answers through a
+/// channel every request, partial response and event that the webview
+/// would have received.
+pub(crate) struct Headless {
+    container: Arc<WebViewContainer>,
+    sender: UnboundedSender<String>,
+}
+
+impl Actor for Headless {
+    type Context = Context<Self>;
+}
+
+impl Handler<Received> for Headless {
+    type Result = ();
+
+    fn handle(&mut self, msg: Received, ctx: &mut Self::Context) -> Self::Result {
+        let caller = ctx.address().recipient();
+        let sender = self.sender.clone();
+        let responses = self.container.dispatch(&*msg.0, caller);
+        ctx.spawn(
+            responses
+                .into_actor(self)
+                .map(move |send, _, _| {
+                    sender.unbounded_send(send).ok();
+                })
+                .finish(),
+        );
+    }
+}
+
+impl Handler<AppString> for Headless {
+    type Result = ();
+
+    fn handle(&mut self, msg: AppString, _: &mut Self::Context) -> Self::Result {
+        let send = self.container.notification(msg.0);
+        self.sender.unbounded_send(send).ok();
+    }
+}
+
+pub struct HeadlessBuilder {
+    pub(crate) container: WebViewBuilderContainer,
+}
+
+impl Application for HeadlessBuilder {
+    type Result = HeadlessApp;
+
+    fn finish(mut self) -> Self::Result {
+        let (sender, receiver) = mpsc::unbounded();
+        let mut lifecycle = self.container.take_lifecycle();
+        let container = Arc::new(WebViewBuilderContainer::finalize(self.container));
+        Arbiter::spawn(lifecycle.start(&container.shared()));
+        let dispatcher = Headless {
+            container: container.clone(),
+            sender,
+        }
+        .start();
+        HeadlessApp {
+            dispatcher,
+            receiver,
+            container,
+            lifecycle,
+        }
+    }
+}
+
+/// In-process channel to an app: requests go in as JSON with `send` and
+/// every message the app emits comes out, in order, from `recv`.
+pub struct HeadlessApp {
+    dispatcher: Addr<Headless>,
+    receiver: UnboundedReceiver<String>,
+    container: Arc<WebViewContainer>,
+    lifecycle: Lifecycle,
+}
+
+impl HeadlessApp {
+    pub fn send<S: Into<String>>(&self, request: S) {
+        self.dispatcher.do_send(Received(request.into()));
+    }
+
+    pub async fn recv(&mut self) -> Option<String> {
+        self.receiver.next().await
+    }
+
+    /// Sends the requests of a recording made with `record` through the
+    /// app, one after the other, and compares the responses to the
+    /// recorded ones.
+    pub async fn replay<P: AsRef<Path>>(&self, path: P) -> io::Result<ReplayReport> {
+        let recording = read_recording(path)?;
+        let caller = self.dispatcher.clone().recipient();
+        Ok(record::replay(&self.container, caller, recording).await)
+    }
+
+    /// Refuses new requests, drains the ones in flight and closes the
+    /// app like a closed webview would.
+    pub async fn shutdown(self) -> ExitReport {
+        let shared = self.container.shared();
+        self.lifecycle
+            .shutdown(&shared, self.container.tracker())
+            .await
+    }
+}
@@ -0,0 +1,231 @@
+use std::io;
+use std::net::SocketAddr;
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+
+use actix::prelude::*;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::HttpServer;
+use actix_web_actors::ws;
+use web_view::Content;
+
+use crate::Application;
+
+use super::assets::Assets;
+use super::container::WebViewBuilderContainer;
+use super::container::WebViewContainer;
+use super::lifecycle::ExitReport;
+use super::lifecycle::Lifecycle;
+use super::AppString;
+
+/// Stands in for the webview's `external.invoke` and `sendToElm` glue,
+/// carrying the same messages over the `/ws` socket.
+const BRIDGE: &str = r#"<script type="text/javascript">
+(function () {
+    var socket = new WebSocket("ws://" + location.host + "/ws");
+    var pending = [];
+    socket.onopen = function () {
+        pending.forEach(function (msg) { socket.send(msg); });
+        pending = [];
+    };
+    socket.onmessage = function (event) { window.sendToElm(JSON.parse(event.data)); };
+    window.external = {
+        invoke: function (msg) {
+            socket.readyState === WebSocket.OPEN ? socket.send(msg) : pending.push(msg);
+        }
+    };
+})();
+</script>"#;
+
+struct Session {
+    container: Arc<WebViewContainer>,
+}
+
+impl Actor for Session {
+    type Context = ws::WebsocketContext<Self>;
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Session {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(ws::Message::Text(text)) => {
+                let caller = ctx.address().recipient();
+                let responses = self.container.dispatch(&*text, caller);
+                ctx.spawn(
+                    responses
+                        .into_actor(self)
+                        .map(|send, _, ctx| ctx.text(send))
+                        .finish(),
+                );
+            }
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Err(_) => ctx.stop(),
+            _ => (),
+        }
+    }
+}
+
+impl Handler<AppString> for Session {
+    type Result = ();
+
+    fn handle(&mut self, msg: AppString, ctx: &mut Self::Context) -> Self::Result {
+        ctx.text(self.container.notification(msg.0));
+    }
+}
+
+async fn index(page: web::Data<String>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(page.get_ref().clone())
+}
+
+async fn asset(name: web::Path<String>, assets: web::Data<Assets>) -> HttpResponse {
+    match assets.get(&name) {
+        Some(asset) => HttpResponse::Ok()
+            .content_type(asset.mime())
+            .body(asset.bytes()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+async fn socket(
+    req: HttpRequest,
+    stream: web::Payload,
+    container: web::Data<Arc<WebViewContainer>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let container = container.get_ref().clone();
+    ws::start(Session { container }, &req, stream)
+}
+
+fn with_bridge(html: &str) -> String {
+    match html.find("<head>") {
+        Some(index) => {
+            let index = index + "<head>".len();
+            format!("{}{}{}", &html[..index], BRIDGE, &html[index..])
+        }
+        None => format!("{}{}", BRIDGE, html),
+    }
+}
+
+/// Serves the app's content over HTTP and its apis over a WebSocket at
+/// `/ws`, so the same app can be opened from a regular browser.
+pub struct HttpBuilder<Addr, Cont> {
+    pub(crate) container: WebViewBuilderContainer,
+    pub(crate) content: Option<Content<Cont>>,
+    pub(crate) assets: Assets,
+    pub(crate) addr: Addr,
+    pub(crate) hot_reload: bool,
+}
+
+/// The page, the assets and the socket, as every server worker serves
+/// them.
+#[derive(Clone)]
+pub(crate) struct Routes {
+    page: web::Data<String>,
+    assets: web::Data<Assets>,
+    container: web::Data<Arc<WebViewContainer>>,
+}
+
+impl Routes {
+    pub(crate) fn configure(&self, config: &mut web::ServiceConfig) {
+        config
+            .app_data(self.page.clone())
+            .app_data(self.container.clone())
+            .app_data(self.assets.clone())
+            .route("/", web::get().to(index))
+            .route("/assets/{name:.*}", web::get().to(asset))
+            .route("/ws", web::get().to(socket));
+    }
+}
+
+impl<Addr, Cont> HttpBuilder<Addr, Cont>
+where
+    Cont: AsRef<str>,
+{
+    /// Fails for a `Content::Url`: a page loaded from elsewhere has no
+    /// bridge to reach the apis over `/ws`. Fails as well with a
+    /// `HotReload`, which only the webview reloads.
+    pub(crate) fn routes(self) -> io::Result<(Routes, Addr)> {
+        let HttpBuilder {
+            container,
+            content,
+            assets,
+            addr,
+            hot_reload,
+        } = self;
+        if hot_reload {
+            let message = "Unable to hot reload over HTTP, use the webview";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        let page = match content {
+            Some(Content::Html(html)) => with_bridge(html.as_ref()),
+            Some(Content::Url(url)) => {
+                let message = format!(
+                    "Unable to serve {} over HTTP, use a Content::Html or the shell",
+                    url.as_ref()
+                );
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            }
+            None => with_bridge(""),
+        };
+        let routes = Routes {
+            page: web::Data::new(page),
+            assets: web::Data::new(assets),
+            container: web::Data::new(Arc::new(WebViewBuilderContainer::finalize(container))),
+        };
+        Ok((routes, addr))
+    }
+}
+
+impl<Addr, Cont> Application for HttpBuilder<Addr, Cont>
+where
+    Addr: ToSocketAddrs,
+    Cont: AsRef<str>,
+{
+    type Result = io::Result<HttpApp>;
+
+    fn finish(mut self) -> Self::Result {
+        let lifecycle = self.container.take_lifecycle();
+        let (routes, addr) = self.routes()?;
+        Ok(HttpApp {
+            routes,
+            addrs: addr.to_socket_addrs()?.collect(),
+            lifecycle,
+        })
+    }
+}
+
+/// A built http app, ready to `run`.
+pub struct HttpApp {
+    routes: Routes,
+    addrs: Vec<SocketAddr>,
+    lifecycle: Lifecycle,
+}
+
+impl HttpApp {
+    /// Runs the `on_start` hooks, then serves the app until the server is
+    /// stopped by a signal. The app is then shut down gracefully.
+    pub async fn run(mut self) -> io::Result<ExitReport> {
+        let container = self.routes.container.get_ref().clone();
+        let shared = container.shared();
+        self.lifecycle.start(&shared).await;
+        let routes = self.routes;
+        let result = match HttpServer::new(move || {
+            let routes = routes.clone();
+            actix_web::App::new().configure(move |config| routes.configure(config))
+        })
+        .bind(&self.addrs[..])
+        {
+            Ok(server) => server.run().await,
+            Err(err) => Err(err),
+        };
+        let report = self.lifecycle.shutdown(&shared, container.tracker()).await;
+        result.map(|_| report)
+    }
+}
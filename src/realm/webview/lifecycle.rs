@@ -0,0 +1,182 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use actix::prelude::*;
+use futures::future::join_all;
+use futures::future::LocalBoxFuture;
+use futures::prelude::*;
+use serde::Serialize;
+
+use super::container::ActorContainer;
+
+/// Sent to every actor loaded with `closable` once the app stopped
+/// answering requests, for it to flush its work and close its resources.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Close;
+
+pub struct WebViewClosableActor<A: Actor>(pub(crate) Addr<A>);
+
+type Hook = Box<dyn FnOnce(&ActorContainer) -> LocalBoxFuture<'static, ()>>;
+
+pub(crate) struct Lifecycle {
+    on_start: Vec<Hook>,
+    on_close: Vec<Hook>,
+    closables: Vec<Recipient<Close>>,
+    deadline: Duration,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Lifecycle {
+            on_start: vec![],
+            on_close: vec![],
+            closables: vec![],
+            deadline: Duration::from_secs(5),
+        }
+    }
+}
+
+fn hook<F, Fut>(f: F) -> Hook
+where
+    F: FnOnce(&ActorContainer) -> Fut + 'static,
+    Fut: Future<Output = ()> + 'static,
+{
+    Box::new(move |container| f(container).boxed_local())
+}
+
+impl Lifecycle {
+    pub(crate) fn on_start<F, Fut>(&mut self, f: F)
+    where
+        F: FnOnce(&ActorContainer) -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.on_start.push(hook(f));
+    }
+
+    pub(crate) fn on_close<F, Fut>(&mut self, f: F)
+    where
+        F: FnOnce(&ActorContainer) -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.on_close.push(hook(f));
+    }
+
+    pub(crate) fn closable(&mut self, recipient: Recipient<Close>) {
+        self.closables.push(recipient);
+    }
+
+    pub(crate) fn deadline(&mut self, deadline: Duration) {
+        self.deadline = deadline;
+    }
+
+    pub(crate) fn start(&mut self, container: &ActorContainer) -> impl Future<Output = ()> {
+        let hooks = self.on_start.drain(..).map(|hook| hook(container));
+        join_all(hooks.collect::<Vec<_>>()).map(|_| ())
+    }
+
+    /// Stops accepting requests, waits for the ones in flight until the
+    /// deadline, then runs the `on_close` hooks and closes the actors.
+    pub(crate) async fn shutdown(
+        self,
+        container: &ActorContainer,
+        tracker: &Tracker,
+    ) -> ExitReport {
+        let started = Instant::now();
+        tracker.closing.store(true, Ordering::SeqCst);
+        let pending = tracker.in_flight();
+        while tracker.in_flight() > 0 && started.elapsed() < self.deadline {
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+        let abandoned = tracker.in_flight();
+        let remaining = self
+            .deadline
+            .checked_sub(started.elapsed())
+            .unwrap_or_default();
+        let Lifecycle {
+            on_close,
+            closables,
+            ..
+        } = self;
+        let hooks = on_close.into_iter().map(|hook| hook(container));
+        let closing = join_all(hooks.collect::<Vec<_>>())
+            .then(|_| join_all(closables.iter().map(|actor| actor.send(Close))));
+        let closed = match tokio::time::timeout(remaining, closing).await {
+            Ok(results) => results.iter().filter(|result| result.is_ok()).count(),
+            Err(_) => 0,
+        };
+        ExitReport {
+            uptime: tracker.started.elapsed(),
+            handled: tracker.handled.load(Ordering::SeqCst),
+            drained: pending.saturating_sub(abandoned),
+            abandoned,
+            closed,
+            shutdown: started.elapsed(),
+        }
+    }
+}
+
+/// Counts the requests of a running app, and refuses new ones once it is
+/// shutting down.
+pub(crate) struct Tracker {
+    started: Instant,
+    closing: AtomicBool,
+    in_flight: Arc<AtomicUsize>,
+    handled: AtomicU64,
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Tracker {
+            started: Instant::now(),
+            closing: AtomicBool::new(false),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            handled: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Tracker {
+    pub(crate) fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Counts a request as in flight until the returned guard is dropped.
+    pub(crate) fn enter(&self) -> InFlight {
+        self.handled.fetch_add(1, Ordering::SeqCst);
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(self.in_flight.clone())
+    }
+}
+
+pub(crate) struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// What happened to an app between its start and its shutdown.
+#[derive(Serialize, Clone, Debug)]
+pub struct ExitReport {
+    pub uptime: Duration,
+    /// Messages received while the app was running.
+    pub handled: u64,
+    /// Messages still in flight at shutdown that were answered in time.
+    pub drained: usize,
+    /// Messages still in flight when the deadline passed.
+    pub abandoned: usize,
+    /// Actors loaded with `closable` that acknowledged `Close`.
+    pub closed: usize,
+    pub shutdown: Duration,
+}
@@ -0,0 +1,296 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use actix::prelude::*;
+use futures::future::join_all;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::api::malformed_payload;
+use super::error;
+use super::errors::ApiError;
+use super::success;
+use super::ApiResponse;
+use super::ErrorKind;
+use super::InvokeRequest;
+
+/// Upper bounds of the latency buckets, in milliseconds.
+const BUCKETS: [u64; 12] = [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct Bucket {
+    /// `None` for the bucket counting every observation.
+    pub le_ms: Option<u64>,
+    pub count: u64,
+}
+
+/// Cumulative latency buckets, like Prometheus histograms.
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct Histogram {
+    pub buckets: Vec<Bucket>,
+    pub sum_ms: f64,
+    pub count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        let bounds = BUCKETS.iter().map(|&le_ms| Some(le_ms)).chain(Some(None));
+        Histogram {
+            buckets: bounds.map(|le_ms| Bucket { le_ms, count: 0 }).collect(),
+            sum_ms: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&mut self, latency: Duration) {
+        let millis = latency.as_secs_f64() * 1000.0;
+        for bucket in self.buckets.iter_mut() {
+            let within = match bucket.le_ms {
+                Some(le_ms) => millis <= le_ms as f64,
+                None => true,
+            };
+            if within {
+                bucket.count += 1;
+            }
+        }
+        self.sum_ms += millis;
+        self.count += 1;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    Error,
+    Malformed,
+    NotFound,
+}
+
+impl From<&ApiResponse> for Outcome {
+    fn from(response: &ApiResponse) -> Self {
+        match response.kind() {
+            _ if !response.is_error() => Outcome::Ok,
+            Some(ErrorKind::Payload) => Outcome::Malformed,
+            Some(ErrorKind::NotFound) => Outcome::NotFound,
+            _ => Outcome::Error,
+        }
+    }
+}
+
+/// Counters and latencies of one api key or one prepared statement.
+/// Malformed payloads and not found apis also count as errors.
+#[derive(Serialize, JsonSchema, Clone, Debug, Default)]
+pub struct Metrics {
+    pub requests: u64,
+    pub errors: u64,
+    pub malformed: u64,
+    pub not_found: u64,
+    pub in_flight: u64,
+    /// Partial responses of streaming apis.
+    pub parts: u64,
+    pub latency: Histogram,
+}
+
+impl Metrics {
+    pub fn enter(&mut self) {
+        self.in_flight += 1;
+    }
+
+    /// Records a finished request, leaving `in_flight` if it was entered.
+    pub fn observe(&mut self, latency: Duration, outcome: Outcome) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+        self.requests += 1;
+        self.latency.observe(latency);
+        match outcome {
+            Outcome::Ok => return,
+            Outcome::Malformed => self.malformed += 1,
+            Outcome::NotFound => self.not_found += 1,
+            Outcome::Error => (),
+        }
+        self.errors += 1;
+    }
+}
+
+/// Metrics by family, like `api` or `statement`, then by key.
+#[derive(Serialize, JsonSchema, Clone, Debug, Default)]
+pub struct MetricsSnapshot(pub BTreeMap<String, BTreeMap<String, Metrics>>);
+
+/// Name suffix, Prometheus type and value of a counter.
+type Counter = (&'static str, &'static str, fn(&Metrics) -> u64);
+
+impl MetricsSnapshot {
+    /// The snapshot in the Prometheus text format, every family prefixed
+    /// with `realm_` and every key as a `key` label.
+    pub fn prometheus(&self) -> String {
+        let mut text = String::new();
+        for (family, metrics) in self.0.iter() {
+            let name = format!("realm_{}", family);
+            let counters: [Counter; 6] = [
+                ("requests_total", "counter", |metrics| metrics.requests),
+                ("errors_total", "counter", |metrics| metrics.errors),
+                ("malformed_total", "counter", |metrics| metrics.malformed),
+                ("not_found_total", "counter", |metrics| metrics.not_found),
+                ("in_flight", "gauge", |metrics| metrics.in_flight),
+                ("parts_total", "counter", |metrics| metrics.parts),
+            ];
+            for (suffix, kind, value) in counters.iter() {
+                writeln!(text, "# TYPE {}_{} {}", name, suffix, kind).unwrap();
+                for (key, metrics) in metrics.iter() {
+                    let value = value(metrics);
+                    writeln!(
+                        text,
+                        "{}_{}{{key=\"{}\"}} {}",
+                        name,
+                        suffix,
+                        label(key),
+                        value
+                    )
+                    .unwrap();
+                }
+            }
+            writeln!(text, "# TYPE {}_latency_seconds histogram", name).unwrap();
+            for (key, metrics) in metrics.iter() {
+                let key = label(key);
+                for bucket in metrics.latency.buckets.iter() {
+                    let le = match bucket.le_ms {
+                        Some(le_ms) => (le_ms as f64 / 1000.0).to_string(),
+                        None => "+Inf".into(),
+                    };
+                    writeln!(
+                        text,
+                        "{}_latency_seconds_bucket{{key=\"{}\",le=\"{}\"}} {}",
+                        name, key, le, bucket.count
+                    )
+                    .unwrap();
+                }
+                let latency = &metrics.latency;
+                writeln!(
+                    text,
+                    "{}_latency_seconds_sum{{key=\"{}\"}} {}",
+                    name,
+                    key,
+                    latency.sum_ms / 1000.0
+                )
+                .unwrap();
+                writeln!(
+                    text,
+                    "{}_latency_seconds_count{{key=\"{}\"}} {}",
+                    name, key, latency.count
+                )
+                .unwrap();
+            }
+        }
+        text
+    }
+}
+
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) enum Observe {
+    Enter(String),
+    Finish(String, Duration, Outcome),
+    Part(String),
+}
+
+/// Asks an actor loaded with `metered` for its metrics, as a family name
+/// and the metrics of each of its keys.
+#[derive(Message)]
+#[rtype(result = "(String, BTreeMap<String, Metrics>)")]
+pub struct Collect;
+
+pub struct WebViewMeteredActor<A: Actor>(pub(crate) Addr<A>);
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct Source(pub(crate) Recipient<Collect>);
+
+#[derive(Deserialize, JsonSchema, Default)]
+pub struct MetricsRequest {
+    /// Also writes the metrics to this file in the Prometheus text format.
+    #[serde(default)]
+    prometheus: Option<PathBuf>,
+}
+
+/// Counts the requests of every api key, answered by `realm/metrics` with
+/// the metrics of the `metered` actors. It is loaded in the
+/// `ActorContainer` next to the `StatusBoard`.
+#[derive(Default)]
+pub struct MetricsBoard {
+    apis: BTreeMap<String, Metrics>,
+    sources: Vec<Recipient<Collect>>,
+}
+
+impl Actor for MetricsBoard {
+    type Context = Context<Self>;
+}
+
+impl Handler<Observe> for MetricsBoard {
+    type Result = ();
+
+    fn handle(&mut self, msg: Observe, _: &mut Self::Context) -> Self::Result {
+        match msg {
+            Observe::Enter(api_key) => self.apis.entry(api_key).or_default().enter(),
+            Observe::Finish(api_key, latency, outcome) => self
+                .apis
+                .entry(api_key)
+                .or_default()
+                .observe(latency, outcome),
+            Observe::Part(api_key) => self.apis.entry(api_key).or_default().parts += 1,
+        }
+    }
+}
+
+impl Handler<Source> for MetricsBoard {
+    type Result = ();
+
+    fn handle(&mut self, msg: Source, _: &mut Self::Context) -> Self::Result {
+        self.sources.push(msg.0);
+    }
+}
+
+impl Handler<InvokeRequest> for MetricsBoard {
+    type Result = ResponseFuture<ApiResponse>;
+
+    fn handle(&mut self, msg: InvokeRequest, _: &mut Self::Context) -> Self::Result {
+        let body = msg.body;
+        let (api_name, id) = (body.api_name, body.id);
+        let request = match body.payload {
+            serde_json::Value::Null => Ok(MetricsRequest::default()),
+            ref payload => serde_json::from_value::<MetricsRequest>(payload.clone()),
+        };
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => {
+                let response = malformed_payload(api_name, id, &body.payload, e);
+                return Box::pin(async { response });
+            }
+        };
+        let mut snapshot = MetricsSnapshot::default();
+        snapshot.0.insert("api".into(), self.apis.clone());
+        let collects = join_all(self.sources.iter().map(|source| source.send(Collect)));
+        Box::pin(async move {
+            for (family, metrics) in collects.await.into_iter().filter_map(Result::ok) {
+                snapshot.0.entry(family).or_default().extend(metrics);
+            }
+            if let Some(path) = request.prometheus {
+                if let Err(err) = std::fs::write(&path, snapshot.prometheus()) {
+                    let message = format!("Unable to write {}: {}", path.display(), err);
+                    return error(api_name, id, &ApiError::new("metrics/dump", message));
+                }
+            }
+            success(api_name, id, &snapshot)
+        })
+    }
+}
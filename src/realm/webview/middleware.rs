@@ -1 +1,68 @@
+use std::sync::Arc;
 
+use actix::prelude::*;
+use futures::future::BoxFuture;
+use futures::prelude::*;
+
+use super::api::mailbox_error;
+use super::ApiResponse;
+use super::InvokeRequest;
+
+/// A link in the dispatch chain. Every registered middleware receives the
+/// request before the api does and decides whether to forward it through
+/// `next`, answer it directly, or post-process the answer coming back.
+pub trait Middleware: Send + Sync + 'static {
+    fn call(&self, req: InvokeRequest, next: Next) -> BoxFuture<'static, ApiResponse>;
+}
+
+impl<F, Fut> Middleware for F
+where
+    F: Fn(InvokeRequest, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ApiResponse> + Send + 'static,
+{
+    fn call(&self, req: InvokeRequest, next: Next) -> BoxFuture<'static, ApiResponse> {
+        self(req, next).boxed()
+    }
+}
+
+pub type MiddlewareChain = Arc<[Box<dyn Middleware>]>;
+
+/// The rest of the chain, ending with the api the request was routed to.
+pub struct Next {
+    chain: MiddlewareChain,
+    index: usize,
+    api: Recipient<InvokeRequest>,
+}
+
+impl Next {
+    pub(crate) fn new(chain: MiddlewareChain, api: Recipient<InvokeRequest>) -> Self {
+        Next {
+            chain,
+            index: 0,
+            api,
+        }
+    }
+
+    pub fn call(self, req: InvokeRequest) -> BoxFuture<'static, ApiResponse> {
+        let Next { chain, index, api } = self;
+        match chain.get(index) {
+            Some(middleware) => {
+                let next = Next {
+                    chain: chain.clone(),
+                    index: index + 1,
+                    api,
+                };
+                middleware.call(req, next)
+            }
+            None => {
+                let api_name = req.body().api_name().clone();
+                let id = req.body().id().cloned();
+                api.send(req)
+                    .map(move |res| res.unwrap_or_else(|err| mailbox_error(api_name, id, err)))
+                    .boxed()
+            }
+        }
+    }
+}
+
+pub struct WebViewMiddleware<M>(pub(crate) M);
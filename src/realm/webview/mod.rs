@@ -1,43 +1,116 @@
+use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::sync::Arc;
+use std::time::Instant;
 
 use actix::prelude::*;
+use futures::channel::mpsc::UnboundedSender;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::adapters::RetryPolicy;
+
 use self::api::WebViewApi;
 use self::api::WebViewApiFactory;
 use self::api::WebViewLazyApi;
 use self::container::ActorContainer;
+use self::describe::ApiSchema;
+use self::extract::WebViewShared;
+use self::lifecycle::Close;
+use self::lifecycle::WebViewClosableActor;
+use self::metrics::Collect;
+use self::metrics::WebViewMeteredActor;
+use self::middleware::Middleware;
+use self::middleware::WebViewMiddleware;
 use self::prelude::WebViewLoadableActor;
+use self::router::RouteParams;
+use self::stream::StreamPart;
+use self::supervise::Restart;
 
 mod api;
 pub mod app;
+mod assets;
+mod builtin;
 pub(crate) mod container;
+mod context;
+mod describe;
+mod devtools;
+mod errors;
+mod events;
+mod extract;
+mod headless;
+#[cfg(feature = "http")]
+mod http;
+mod lifecycle;
+mod metrics;
 pub mod middleware;
-#[cfg(tests)]
+mod protocol;
+mod record;
+mod reload;
+mod router;
+mod shell;
+mod stream;
+mod supervise;
+#[cfg(test)]
 mod tests;
 
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum RequestId {
+    Number(i64),
+    Text(String),
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestId::Number(id) => write!(f, "{}", id),
+            RequestId::Text(id) => write!(f, "{}", id),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct InvokeBody {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) id: Option<RequestId>,
     pub(crate) api_name: String,
     pub(crate) payload: Value,
 }
 
 #[derive(Clone, Message)]
-#[rtype(result = "()")]
+#[rtype(result = "ApiResponse")]
 pub struct InvokeRequest {
     pub(crate) body: InvokeBody,
     pub(crate) caller: Recipient<AppString>,
     pub(crate) data: Arc<ActorContainer>,
+    pub(crate) params: RouteParams,
+    pub(crate) parts: UnboundedSender<ApiResponse>,
+    pub(crate) route: String,
 }
 
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct Cancel(pub(crate) RequestId);
+
 #[derive(Serialize)]
 #[serde(untagged)]
 pub enum ApiResponse {
     OpResponse {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<RequestId>,
+        api_name: String,
+        #[serde(flatten)]
+        body: ApiResponseBody,
+    },
+    OpStream {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<RequestId>,
         api_name: String,
         #[serde(flatten)]
+        part: StreamPart,
+        #[serde(flatten)]
         body: ApiResponseBody,
     },
     OpDoNothing(String),
@@ -51,9 +124,22 @@ impl InvokeRequest {
     pub fn container(self) -> Arc<ActorContainer> {
         self.data
     }
+
+    pub fn params(&self) -> &RouteParams {
+        &self.params
+    }
 }
 
 impl InvokeBody {
+    pub fn id(&self) -> Option<&RequestId> {
+        self.id.as_ref()
+    }
+
+    pub fn with_id(mut self, id: RequestId) -> Self {
+        self.id = Some(id);
+        self
+    }
+
     pub fn api_name(&self) -> &String {
         &self.api_name
     }
@@ -68,21 +154,87 @@ impl ApiResponse {
         match self {
             ApiResponse::OpDoNothing(ref name) => format!("[api_name = {}]", name),
             ApiResponse::OpResponse {
+                id: _,
                 api_name: _,
-                body:
-                    ApiResponseBody {
-                        ref payload,
-                        error: _,
-                    },
+                body: ApiResponseBody { ref payload, .. },
+            }
+            | ApiResponse::OpStream {
+                body: ApiResponseBody { ref payload, .. },
+                ..
             } => serde_json::to_string(payload).unwrap(),
         }
     }
+
+    pub fn api_name(&self) -> &str {
+        match self {
+            ApiResponse::OpDoNothing(ref api_name)
+            | ApiResponse::OpResponse { ref api_name, .. }
+            | ApiResponse::OpStream { ref api_name, .. } => api_name,
+        }
+    }
+
+    pub fn id(&self) -> Option<&RequestId> {
+        match self {
+            ApiResponse::OpDoNothing(_) => None,
+            ApiResponse::OpResponse { ref id, .. } | ApiResponse::OpStream { ref id, .. } => {
+                id.as_ref()
+            }
+        }
+    }
+
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            ApiResponse::OpDoNothing(_) => None,
+            ApiResponse::OpResponse { ref body, .. } | ApiResponse::OpStream { ref body, .. } => {
+                body.kind
+            }
+        }
+    }
+
+    pub(crate) fn with_kind(mut self, kind: ErrorKind) -> Self {
+        match self {
+            ApiResponse::OpDoNothing(_) => (),
+            ApiResponse::OpResponse { ref mut body, .. }
+            | ApiResponse::OpStream { ref mut body, .. } => body.kind = Some(kind),
+        }
+        self
+    }
+
+    pub fn is_partial(&self) -> bool {
+        matches!(self, ApiResponse::OpStream { part, .. } if !part.done)
+    }
+
+    pub fn is_error(&self) -> bool {
+        match self {
+            ApiResponse::OpDoNothing(_) => false,
+            ApiResponse::OpResponse { ref body, .. } | ApiResponse::OpStream { ref body, .. } => {
+                body.error
+            }
+        }
+    }
 }
 
 #[derive(Serialize)]
 pub struct ApiResponseBody {
     pub(crate) error: bool,
     pub(crate) payload: Option<Value>,
+    #[serde(skip)]
+    pub(crate) kind: Option<ErrorKind>,
+}
+
+/// Where a request failed, kept out of the response's JSON so that each
+/// protocol can report it its own way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    Parse,
+    Request,
+    NotFound,
+    Payload,
+    Unreachable,
+    Timeout,
+    Cancelled,
+    Panic,
+    Api,
 }
 
 #[derive(Message)]
@@ -97,17 +249,20 @@ impl From<String> for AppString {
 
 pub fn request<Data: Serialize>(api_name: String, data: &Data) -> InvokeBody {
     InvokeBody {
+        id: None,
         api_name,
         payload: serde_json::to_value(data).unwrap(),
     }
 }
 
-pub fn error<Data: Serialize>(api_name: String, data: &Data) -> ApiResponse {
+pub fn error<Data: Serialize>(api_name: String, id: Option<RequestId>, data: &Data) -> ApiResponse {
     ApiResponse::OpResponse {
+        id,
         api_name: api_name.into(),
         body: ApiResponseBody {
             error: true,
             payload: serde_json::to_value(data).ok(),
+            kind: Some(ErrorKind::Api),
         },
     }
 }
@@ -116,12 +271,18 @@ pub fn void(api_name: String) -> ApiResponse {
     ApiResponse::OpDoNothing(api_name)
 }
 
-pub fn success<Data: Serialize>(api_name: String, data: &Data) -> ApiResponse {
+pub fn success<Data: Serialize>(
+    api_name: String,
+    id: Option<RequestId>,
+    data: &Data,
+) -> ApiResponse {
     ApiResponse::OpResponse {
+        id,
         api_name: api_name.into(),
         body: ApiResponseBody {
             error: false,
             payload: serde_json::to_value(data).ok(),
+            kind: None,
         },
     }
 }
@@ -130,7 +291,14 @@ pub fn web_view_api<A>(api_key: &'static str, api: A) -> WebViewApi<A>
 where
     WebViewApi<A>: KeyedActor,
 {
-    WebViewApi { api_key, api }
+    WebViewApi {
+        api_key,
+        api,
+        timeout: None,
+        in_flight: HashMap::new(),
+        restart: Restart::default(),
+        restarts: 0,
+    }
 }
 
 pub fn lazy<F>(api_key: &'static str, factory: F) -> WebViewLazyApi<F>
@@ -139,26 +307,75 @@ where
 {
     WebViewLazyApi {
         api_key,
-        factory,
+        factory: Arc::new(factory),
         api: None,
+        canceller: None,
+        timeout: None,
+        retry: RetryPolicy::attempts(1),
+        idle: None,
+        invalidator: None,
+        generation: 0,
+        last_used: Instant::now(),
+        restart: Restart::default(),
+        restarts: 0,
+        initializing: None,
+        init_abort: None,
     }
 }
 
-pub fn api_init_with<F, A>(f: F) -> WebViewApiFactory<F, A>
-{
-    WebViewApiFactory(f, PhantomData)
+pub fn api_init_with<F, A>(f: F) -> WebViewApiFactory<F, A> {
+    WebViewApiFactory(f, PhantomData, None)
 }
 
 pub fn actor<A: Actor>(addr: Addr<A>) -> WebViewLoadableActor<A> {
     WebViewLoadableActor(addr)
 }
 
-pub trait KeyedActor: Actor<Context = Context<Self>> + Handler<InvokeRequest> {
+pub fn closable<A: Actor + Handler<Close>>(addr: Addr<A>) -> WebViewClosableActor<A> {
+    WebViewClosableActor(addr)
+}
+
+pub fn metered<A: Actor + Handler<Collect>>(addr: Addr<A>) -> WebViewMeteredActor<A> {
+    WebViewMeteredActor(addr)
+}
+
+pub fn shared<T: Send + Sync + 'static>(service: T) -> WebViewShared<T> {
+    WebViewShared(service)
+}
+
+pub fn middleware<M: Middleware>(middleware: M) -> WebViewMiddleware<M> {
+    WebViewMiddleware(middleware)
+}
+
+pub trait KeyedActor:
+    Actor<Context = Context<Self>> + Supervised + Handler<InvokeRequest> + Handler<Cancel>
+{
     fn api_key(&self) -> &'static str;
+
+    fn schema(&self) -> ApiSchema;
 }
 
 pub mod prelude {
     pub use super::api::*;
     pub use super::app::*;
+    pub use super::assets::*;
+    pub use super::context::*;
+    pub use super::describe::*;
+    pub use super::devtools::*;
+    pub use super::errors::*;
+    pub use super::events::*;
+    pub use super::extract::*;
+    pub use super::headless::*;
+    #[cfg(feature = "http")]
+    pub use super::http::*;
+    pub use super::lifecycle::*;
+    pub use super::metrics::*;
+    pub use super::protocol::*;
+    pub use super::record::*;
+    pub use super::reload::*;
+    pub use super::router::*;
+    pub use super::shell::*;
+    pub use super::stream::*;
+    pub use super::supervise::*;
     pub use super::*;
 }
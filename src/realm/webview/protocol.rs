@@ -0,0 +1,198 @@
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+
+use super::errors::failure;
+use super::ApiResponse;
+use super::ErrorKind;
+use super::InvokeBody;
+use super::RequestId;
+
+/// The envelope spoken with the client. `Native` is the `api_name` /
+/// `payload` protocol described in the README, `JsonRpc` follows the
+/// JSON-RPC 2.0 specification, with methods named after the api keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Native,
+    JsonRpc,
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Protocol::Native
+    }
+}
+
+/// How the answers to a batch of requests are sent: all at once in an
+/// array once every request is done, or each on its own as it completes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Batch {
+    Combined,
+    Individual,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Batch::Combined
+    }
+}
+
+pub(crate) enum Message {
+    Single(Value),
+    Batch(Vec<Value>),
+}
+
+pub(crate) fn read(param: &str) -> Result<Message, ApiResponse> {
+    match serde_json::from_str::<Value>(param) {
+        Ok(Value::Array(values)) if values.is_empty() => Err(failure(
+            "request/failed".into(),
+            None,
+            ErrorKind::Request,
+            "Empty batch",
+        )),
+        Ok(Value::Array(values)) => Ok(Message::Batch(values)),
+        Ok(value) => Ok(Message::Single(value)),
+        Err(err) => Err(failure(
+            "request/failed".into(),
+            None,
+            ErrorKind::Parse,
+            err.to_string(),
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Value,
+    #[serde(default)]
+    id: Option<RequestId>,
+}
+
+impl Protocol {
+    pub(crate) fn parse(self, value: Value) -> Result<InvokeBody, ApiResponse> {
+        let id = value
+            .get("id")
+            .cloned()
+            .and_then(|id| serde_json::from_value(id).ok());
+        let invalid =
+            move |err: String| failure("request/failed".into(), id, ErrorKind::Request, err);
+        match self {
+            Protocol::Native => {
+                serde_json::from_value(value).map_err(|err| invalid(err.to_string()))
+            }
+            Protocol::JsonRpc => match serde_json::from_value::<RpcRequest>(value) {
+                Ok(RpcRequest {
+                    jsonrpc,
+                    method,
+                    params,
+                    id,
+                }) if jsonrpc == "2.0" => Ok(InvokeBody {
+                    id,
+                    api_name: method,
+                    payload: params,
+                }),
+                Ok(RpcRequest { jsonrpc, .. }) => {
+                    Err(invalid(format!("Unsupported jsonrpc version: {}", jsonrpc)))
+                }
+                Err(err) => Err(invalid(err.to_string())),
+            },
+        }
+    }
+
+    /// Encodes the answer to a request, or nothing if the protocol forbids
+    /// answering it (JSON-RPC notifications).
+    pub(crate) fn encode(self, response: &ApiResponse) -> Option<Value> {
+        match self {
+            Protocol::Native => serde_json::to_value(response).ok(),
+            Protocol::JsonRpc => rpc_response(response),
+        }
+    }
+
+    pub(crate) fn encode_batch(self, responses: &[ApiResponse]) -> Option<Value> {
+        let responses: Vec<_> = responses
+            .iter()
+            .filter_map(|response| self.encode(response))
+            .collect();
+        match responses.is_empty() {
+            true => None,
+            false => Some(Value::Array(responses)),
+        }
+    }
+
+    /// Encodes a message sent without being asked for, like an event or a
+    /// stream's partial response.
+    pub(crate) fn notification(self, message: String) -> String {
+        match self {
+            Protocol::Native => message,
+            Protocol::JsonRpc => {
+                let params =
+                    serde_json::from_str::<Value>(&message).unwrap_or(Value::String(message));
+                let method = match params.get("topic") {
+                    Some(_) => "realm/event",
+                    None => "realm/partial",
+                };
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": method,
+                    "params": params
+                })
+                .to_string()
+            }
+        }
+    }
+}
+
+fn rpc_error(kind: ErrorKind) -> (i64, &'static str) {
+    match kind {
+        ErrorKind::Parse => (-32700, "Parse error"),
+        ErrorKind::Request => (-32600, "Invalid Request"),
+        ErrorKind::NotFound => (-32601, "Method not found"),
+        ErrorKind::Payload => (-32602, "Invalid params"),
+        ErrorKind::Unreachable | ErrorKind::Panic => (-32603, "Internal error"),
+        ErrorKind::Api => (-32000, "Server error"),
+        ErrorKind::Timeout => (-32001, "Request timed out"),
+        ErrorKind::Cancelled => (-32002, "Request cancelled"),
+    }
+}
+
+fn rpc_response(response: &ApiResponse) -> Option<Value> {
+    let (id, body) = match response {
+        ApiResponse::OpResponse { id, body, .. } | ApiResponse::OpStream { id, body, .. } => {
+            (id, body)
+        }
+        ApiResponse::OpDoNothing(_) => return None,
+    };
+    match body.kind {
+        None if id.is_none() => None,
+        None => Some(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": body.payload
+        })),
+        Some(ErrorKind::Parse) | Some(ErrorKind::Request) => {
+            Some(rpc_error_response(id, body.kind, &body.payload))
+        }
+        Some(_) if id.is_none() => None,
+        Some(_) => Some(rpc_error_response(id, body.kind, &body.payload)),
+    }
+}
+
+fn rpc_error_response(
+    id: &Option<RequestId>,
+    kind: Option<ErrorKind>,
+    payload: &Option<Value>,
+) -> Value {
+    let (code, message) = rpc_error(kind.unwrap_or(ErrorKind::Api));
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": code,
+            "message": message,
+            "data": payload
+        }
+    })
+}
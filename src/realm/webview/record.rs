@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::LineWriter;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use actix::prelude::*;
+use futures::channel::mpsc;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::container::WebViewContainer;
+use super::ApiResponse;
+use super::AppString;
+use super::InvokeBody;
+
+/// One line of a recording. A request and its response share a `seq`,
+/// timestamps are milliseconds since the Unix epoch.
+#[derive(Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Recorded {
+    Request {
+        seq: u64,
+        timestamp: u64,
+        body: InvokeBody,
+    },
+    Response {
+        seq: u64,
+        timestamp: u64,
+        response: Value,
+    },
+    Notification {
+        timestamp: u64,
+        message: String,
+    },
+}
+
+pub(crate) fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Appends every `Recorded` to a JSON Lines file.
+pub(crate) struct Recorder(LineWriter<File>);
+
+impl Actor for Recorder {
+    type Context = Context<Self>;
+}
+
+impl Handler<Recorded> for Recorder {
+    type Result = ();
+
+    fn handle(&mut self, msg: Recorded, _: &mut Self::Context) -> Self::Result {
+        let line = serde_json::to_string(&msg).unwrap();
+        if let Err(err) = writeln!(self.0, "{}", line) {
+            tracing::warn!(error = %err, "unable to record");
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Recording {
+    recorder: Addr<Recorder>,
+    seq: Arc<AtomicU64>,
+}
+
+impl Recording {
+    pub(crate) fn create(path: &Path) -> io::Result<Self> {
+        Ok(Recording {
+            recorder: Recorder(LineWriter::new(File::create(path)?)).start(),
+            seq: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub(crate) fn request(&self, body: &InvokeBody) -> u64 {
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
+        self.recorder.do_send(Recorded::Request {
+            seq,
+            timestamp: timestamp(),
+            body: body.clone(),
+        });
+        seq
+    }
+
+    pub(crate) fn response(&self, seq: u64, response: &ApiResponse) {
+        self.recorder.do_send(Recorded::Response {
+            seq,
+            timestamp: timestamp(),
+            response: serde_json::to_value(response).unwrap_or_default(),
+        });
+    }
+
+    pub(crate) fn notification(&self, message: &str) {
+        self.recorder.do_send(Recorded::Notification {
+            timestamp: timestamp(),
+            message: message.into(),
+        });
+    }
+}
+
+pub fn read_recording<P: AsRef<Path>>(path: P) -> io::Result<Vec<Recorded>> {
+    let file = BufReader::new(File::open(path)?);
+    file.lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| {
+            let line = line?;
+            serde_json::from_str(&line)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        })
+        .collect()
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Difference {
+    /// Where the responses differ, like `payload.rows[2].name`.
+    pub path: String,
+    pub expected: Value,
+    pub actual: Value,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Mismatch {
+    pub seq: u64,
+    pub api_name: String,
+    pub differences: Vec<Difference>,
+}
+
+/// Responses of a replay compared to the recorded ones.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ReplayReport {
+    pub replayed: usize,
+    pub matched: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+pub fn diff(expected: &Value, actual: &Value) -> Vec<Difference> {
+    let mut differences = vec![];
+    diff_at(String::new(), expected, actual, &mut differences);
+    differences
+}
+
+fn diff_at(path: String, expected: &Value, actual: &Value, differences: &mut Vec<Difference>) {
+    let field = |key: &str| match path.as_str() {
+        "" => key.to_string(),
+        _ => format!("{}.{}", path, key),
+    };
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, value) in expected.iter() {
+                let other = actual.get(key).unwrap_or(&Value::Null);
+                diff_at(field(key), value, other, differences);
+            }
+            for (key, value) in actual
+                .iter()
+                .filter(|(key, _)| !expected.contains_key(*key))
+            {
+                diff_at(field(key), &Value::Null, value, differences);
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            for index in 0..expected.len().max(actual.len()) {
+                diff_at(
+                    format!("{}[{}]", path, index),
+                    expected.get(index).unwrap_or(&Value::Null),
+                    actual.get(index).unwrap_or(&Value::Null),
+                    differences,
+                );
+            }
+        }
+        _ if expected != actual => differences.push(Difference {
+            path,
+            expected: expected.clone(),
+            actual: actual.clone(),
+        }),
+        _ => (),
+    }
+}
+
+/// Sends every recorded request through `container`, one after the other,
+/// and compares each response to the recorded one.
+pub(crate) async fn replay(
+    container: &WebViewContainer,
+    caller: Recipient<AppString>,
+    recording: Vec<Recorded>,
+) -> ReplayReport {
+    let mut requests = vec![];
+    let mut responses = HashMap::new();
+    for recorded in recording {
+        match recorded {
+            Recorded::Request { seq, body, .. } => requests.push((seq, body)),
+            Recorded::Response { seq, response, .. } => {
+                responses.insert(seq, response);
+            }
+            Recorded::Notification { .. } => (),
+        }
+    }
+    let mut report = ReplayReport::default();
+    for (seq, body) in requests {
+        let api_name = body.api_name.clone();
+        let (parts, _) = mpsc::unbounded();
+        let actual = match container.route(body, caller.clone(), parts) {
+            Ok((_, response)) => response.await,
+            Err(response) => response,
+        };
+        let actual = serde_json::to_value(&actual).unwrap_or_default();
+        let expected = responses.remove(&seq).unwrap_or_default();
+        let differences = diff(&expected, &actual);
+        report.replayed += 1;
+        if differences.is_empty() {
+            report.matched += 1;
+        } else {
+            report.mismatches.push(Mismatch {
+                seq,
+                api_name,
+                differences,
+            });
+        }
+    }
+    report
+}
@@ -0,0 +1,123 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use super::shell::script_json;
+
+type Render = Box<dyn Fn() -> io::Result<String> + Send>;
+
+/// Content rendered from files on disk, rendered again whenever one of
+/// the watched files changes. Loaded with `AppBuilder::hot_reload` for
+/// development, it reloads the page in place while the actors keep
+/// running.
+pub struct HotReload {
+    render: Render,
+    watched: Vec<(PathBuf, Option<(SystemTime, u64)>)>,
+    interval: Duration,
+    checked: Option<Instant>,
+}
+
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+impl HotReload {
+    pub fn new<F>(render: F) -> Self
+    where
+        F: Fn() -> io::Result<String> + Send + 'static,
+    {
+        HotReload {
+            render: Box::new(render),
+            watched: vec![],
+            interval: Duration::from_millis(250),
+            checked: None,
+        }
+    }
+
+    pub fn watch<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        let path = path.into();
+        let stamp = stamp(&path);
+        self.watched.push((path, stamp));
+        self
+    }
+
+    /// How often the watched files are checked, every 250ms by default.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn render(&self) -> io::Result<String> {
+        (self.render)()
+    }
+
+    /// Renders the content again when a watched file changed since the
+    /// last poll, checking them at most once per interval.
+    pub fn poll(&mut self) -> Option<io::Result<String>> {
+        match self.checked {
+            Some(checked) if checked.elapsed() < self.interval => return None,
+            _ => self.checked = Some(Instant::now()),
+        }
+        let mut changed = false;
+        for (path, last) in self.watched.iter_mut() {
+            let stamp = stamp(path);
+            if stamp != *last {
+                tracing::debug!(path = %path.display(), "changed");
+                *last = stamp;
+                changed = true;
+            }
+        }
+        if changed {
+            Some(self.render())
+        } else {
+            None
+        }
+    }
+
+    /// Polls the watched files on a thread of its own, handing every new
+    /// rendering to `reload` until it returns false or the returned
+    /// `Watching` is dropped.
+    pub(crate) fn spawn<F>(mut self, reload: F) -> Watching
+    where
+        F: Fn(io::Result<String>) -> bool + Send + 'static,
+    {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let watching = Watching(stopped.clone());
+        thread::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                thread::sleep(self.interval);
+                if let Some(content) = self.poll() {
+                    if !reload(content) {
+                        break;
+                    }
+                }
+            }
+        });
+        watching
+    }
+}
+
+pub(crate) struct Watching(Arc<AtomicBool>);
+
+impl Drop for Watching {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Script replacing the page with `html`, running its scripts again.
+pub(crate) fn reload_script(html: &str) -> String {
+    format!(
+        "document.open();document.write({});document.close();",
+        script_json(html)
+    )
+}
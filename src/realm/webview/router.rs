@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use actix::prelude::*;
+
+use crate::Load;
+
+use super::describe::ApiSchema;
+use super::Cancel;
+use super::InvokeRequest;
+use super::KeyedActor;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Rest,
+}
+
+/// An api key that may hold `{name}` segments, matching any one segment,
+/// and end with `*`, matching one or more segments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoutePattern {
+    key: String,
+    segments: Vec<Segment>,
+}
+
+impl RoutePattern {
+    pub fn parse(key: &str) -> Self {
+        let segments = key
+            .split('/')
+            .map(|segment| match segment {
+                "*" => Segment::Rest,
+                _ if segment.starts_with('{') && segment.ends_with('}') => {
+                    Segment::Param(segment[1..segment.len() - 1].into())
+                }
+                _ => Segment::Literal(segment.into()),
+            })
+            .collect();
+        RoutePattern {
+            key: key.into(),
+            segments,
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.segments
+            .iter()
+            .all(|segment| matches!(segment, Segment::Literal(_)))
+    }
+
+    pub fn matches(&self, api_name: &str) -> Option<RouteParams> {
+        let mut params = RouteParams::default();
+        let mut parts = api_name.split('/');
+        for (index, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Rest => {
+                    let rest = api_name.splitn(index + 1, '/').nth(index)?;
+                    params.0.insert("*".into(), rest.into());
+                    return Some(params);
+                }
+                Segment::Literal(literal) if parts.next()? == literal => (),
+                Segment::Literal(_) => return None,
+                Segment::Param(name) => {
+                    params.0.insert(name.clone(), parts.next()?.into());
+                }
+            }
+        }
+        match parts.next() {
+            Some(_) => None,
+            None => Some(params),
+        }
+    }
+
+    /// More literal segments win, and a trailing `*` loses to `{name}`s.
+    fn specificity(&self) -> (usize, bool) {
+        let literals = self
+            .segments
+            .iter()
+            .filter(|segment| matches!(segment, Segment::Literal(_)))
+            .count();
+        (literals, !self.segments.contains(&Segment::Rest))
+    }
+}
+
+/// Path parameters extracted from the api name by a `RoutePattern`, the
+/// segment matched by `*` is stored under `"*"`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RouteParams(HashMap<String, String>);
+
+impl RouteParams {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(|value| &**value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Api keys resolved by exact match first, then by the most specific
+/// pattern.
+#[derive(Clone)]
+pub struct RouteTable<T> {
+    exact: HashMap<String, T>,
+    patterns: Vec<(RoutePattern, T)>,
+}
+
+impl<T> Default for RouteTable<T> {
+    fn default() -> Self {
+        RouteTable {
+            exact: HashMap::new(),
+            patterns: vec![],
+        }
+    }
+}
+
+impl<T> RouteTable<T> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        RouteTable {
+            exact: HashMap::with_capacity(capacity),
+            patterns: vec![],
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.exact.contains_key(key) || self.patterns.iter().any(|(pattern, _)| pattern.key == key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        let exact = self.exact.keys().map(|key| &**key);
+        exact.chain(self.patterns.iter().map(|(pattern, _)| &*pattern.key))
+    }
+
+    pub fn insert(&mut self, key: &str, value: T) {
+        let pattern = RoutePattern::parse(key);
+        if pattern.is_exact() {
+            self.exact.insert(key.into(), value);
+            return;
+        }
+        self.patterns.retain(|(other, _)| other.key != key);
+        self.patterns.push((pattern, value));
+        self.patterns
+            .sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.specificity()));
+    }
+
+    pub fn get(&self, api_name: &str) -> Option<(&T, RouteParams)> {
+        self.resolve(api_name)
+            .map(|(_, value, params)| (value, params))
+    }
+
+    /// Like `get`, along with the key the matched route was inserted with.
+    pub fn resolve(&self, api_name: &str) -> Option<(&str, &T, RouteParams)> {
+        if let Some((key, value)) = self.exact.get_key_value(api_name) {
+            return Some((key, value, RouteParams::default()));
+        }
+        self.patterns.iter().find_map(|(pattern, value)| {
+            pattern
+                .matches(api_name)
+                .map(|params| (&*pattern.key, value, params))
+        })
+    }
+}
+
+pub(crate) struct Route {
+    pub(crate) key: String,
+    pub(crate) recipient: Recipient<InvokeRequest>,
+    pub(crate) canceller: Recipient<Cancel>,
+    pub(crate) schema: ApiSchema,
+}
+
+/// Apis grouped under a common prefix, like a namespace or a version.
+/// Routers nest, and are loaded into the `AppBuilder` or another router.
+pub struct Router {
+    prefix: String,
+    pub(crate) routes: Vec<Route>,
+}
+
+impl Router {
+    pub(crate) fn prefixed(self) -> impl Iterator<Item = Route> {
+        let prefix = self.prefix;
+        self.routes.into_iter().map(move |route| Route {
+            key: join(&prefix, &route.key),
+            ..route
+        })
+    }
+}
+
+fn join(prefix: &str, key: &str) -> String {
+    match (prefix.trim_end_matches('/'), key.trim_start_matches('/')) {
+        ("", key) => key.into(),
+        (prefix, "") => prefix.into(),
+        (prefix, key) => format!("{}/{}", prefix, key),
+    }
+}
+
+impl<API> Load<API> for Router
+where
+    API: KeyedActor,
+{
+    type Result = Self;
+
+    fn load(mut self, api: API) -> Self::Result {
+        let key = api.api_key().into();
+        let schema = api.schema();
+        let addr = Supervisor::start(move |_| api);
+        self.routes.push(Route {
+            key,
+            recipient: addr.clone().recipient(),
+            canceller: addr.recipient(),
+            schema,
+        });
+        self
+    }
+}
+
+impl Load<Router> for Router {
+    type Result = Self;
+
+    fn load(mut self, router: Router) -> Self::Result {
+        self.routes.extend(router.prefixed());
+        self
+    }
+}
+
+pub fn router(prefix: &str) -> Router {
+    Router {
+        prefix: prefix.into(),
+        routes: vec![],
+    }
+}
+
+/// A router under `v{version}`, so that older clients keep calling the
+/// apis they were built against.
+pub fn versioned(version: u32) -> Router {
+    router(&format!("v{}", version))
+}
@@ -0,0 +1,254 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use super::assets::Assets;
+
+#[derive(Clone, Debug)]
+enum Stylesheet {
+    Inline(String),
+    Link {
+        href: String,
+        integrity: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug)]
+enum Script {
+    Inline(String),
+    Link {
+        src: String,
+        integrity: Option<String>,
+    },
+    Init(Value),
+}
+
+/// The HTML page hosting the Elm app: head metadata, stylesheets, the
+/// mount point and the scripts, escaped as they are rendered.
+#[derive(Clone, Debug)]
+pub struct Shell {
+    lang: Option<String>,
+    title: Option<String>,
+    meta: Vec<(String, String)>,
+    stylesheets: Vec<Stylesheet>,
+    scripts: Vec<Script>,
+    module: String,
+    mount: String,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Shell::new()
+    }
+}
+
+impl Shell {
+    /// A shell with a viewport meta, mounting `Elm.Main` on `#elm`.
+    pub fn new() -> Self {
+        Shell {
+            lang: None,
+            title: None,
+            meta: vec![(
+                "viewport".into(),
+                "width=device-width, initial-scale=1".into(),
+            )],
+            stylesheets: vec![],
+            scripts: vec![],
+            module: "Main".into(),
+            mount: "elm".into(),
+        }
+    }
+
+    pub fn lang(mut self, lang: &str) -> Self {
+        self.lang = Some(lang.into());
+        self
+    }
+
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Adds a `<meta name content>`, replacing the one with the same name.
+    pub fn meta(mut self, name: &str, content: &str) -> Self {
+        match self.meta.iter_mut().find(|(other, _)| other == name) {
+            Some((_, other)) => *other = content.into(),
+            None => self.meta.push((name.into(), content.into())),
+        }
+        self
+    }
+
+    pub fn style(mut self, css: &str) -> Self {
+        self.stylesheets.push(Stylesheet::Inline(css.into()));
+        self
+    }
+
+    /// Links a stylesheet, checked against `integrity` when given.
+    pub fn link_stylesheet(mut self, href: &str, integrity: Option<&str>) -> Self {
+        self.stylesheets.push(Stylesheet::Link {
+            href: href.into(),
+            integrity: integrity.map(Into::into),
+        });
+        self
+    }
+
+    pub fn script(mut self, js: &str) -> Self {
+        self.scripts.push(Script::Inline(js.into()));
+        self
+    }
+
+    /// Links a script, checked against `integrity` when given.
+    pub fn link_script(mut self, src: &str, integrity: Option<&str>) -> Self {
+        self.scripts.push(Script::Link {
+            src: src.into(),
+            integrity: integrity.map(Into::into),
+        });
+        self
+    }
+
+    /// The Elm module initialized by `init`, `Main` by default.
+    pub fn module(mut self, module: &str) -> Self {
+        self.module = module.into();
+        self
+    }
+
+    /// The id of the element Elm mounts on, `elm` by default.
+    pub fn mount(mut self, id: &str) -> Self {
+        self.mount = id.into();
+        self
+    }
+
+    /// Initializes the Elm module with `flags` after the scripts added so
+    /// far, as `var app`, so the scripts added next can use its ports.
+    /// `Value::Null` suits a program taking `()` flags.
+    pub fn init(mut self, flags: Value) -> Self {
+        self.scripts.push(Script::Init(flags));
+        self
+    }
+
+    pub fn render(&self) -> String {
+        self.render_with(&Assets::default())
+    }
+
+    /// Renders the page with the stylesheets and scripts of `assets`
+    /// inlined before the shell's own.
+    pub fn render_with(&self, assets: &Assets) -> String {
+        let mut page = String::from("<!DOCTYPE html>\n");
+        match &self.lang {
+            Some(lang) => page += &format!("<html lang=\"{}\">\n", escape(lang)),
+            None => page += "<html>\n",
+        }
+        page += "<head>\n<meta charset=\"UTF-8\">\n";
+        for (name, content) in self.meta.iter() {
+            page += &format!(
+                "<meta name=\"{}\" content=\"{}\">\n",
+                escape(name),
+                escape(content)
+            );
+        }
+        if let Some(title) = &self.title {
+            page += &format!("<title>{}</title>\n", escape(title));
+        }
+        let styles = assets
+            .iter()
+            .filter(|(_, asset)| asset.is_stylesheet())
+            .map(|(_, asset)| Stylesheet::Inline(assets.inline_stylesheet(asset)));
+        for stylesheet in styles.chain(self.stylesheets.iter().cloned()) {
+            page += &match stylesheet {
+                Stylesheet::Inline(css) => format!("<style>{}</style>\n", raw_text(&css, "style")),
+                Stylesheet::Link { href, integrity } => format!(
+                    "<link rel=\"stylesheet\" href=\"{}\"{}>\n",
+                    escape(&href),
+                    integrity_attributes(&integrity)
+                ),
+            };
+        }
+        page += &format!(
+            "</head>\n<body>\n<div id=\"{}\"></div>\n",
+            escape(&self.mount)
+        );
+        let scripts = assets
+            .iter()
+            .filter(|(_, asset)| asset.is_script())
+            .map(|(_, asset)| Script::Inline(asset.text().unwrap_or_default().into()));
+        for script in scripts.chain(self.scripts.iter().cloned()) {
+            page += &match script {
+                Script::Inline(js) => format!("<script>{}</script>\n", raw_text(&js, "script")),
+                Script::Link { src, integrity } => format!(
+                    "<script src=\"{}\"{}></script>\n",
+                    escape(&src),
+                    integrity_attributes(&integrity)
+                ),
+                Script::Init(flags) => format!("<script>{}</script>\n", self.init_script(&flags)),
+            };
+        }
+        page += "</body>\n</html>\n";
+        page
+    }
+
+    fn init_script(&self, flags: &Value) -> String {
+        let module = self
+            .module
+            .split('.')
+            .map(|name| format!("[{}]", script_json(name)))
+            .collect::<String>();
+        format!(
+            "var app = Elm{}.init({{ node: document.getElementById({}), flags: {} }});",
+            module,
+            script_json(&self.mount),
+            script_json(flags)
+        )
+    }
+}
+
+/// Escapes text and attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&#39;",
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Keeps the content of a `<style>` or `<script>` from closing its
+/// element early, in any case, or opening an HTML comment.
+fn raw_text(content: &str, tag: &str) -> String {
+    let end = format!("</{}", tag);
+    let lowercase = content.to_ascii_lowercase();
+    let mut escaped = String::with_capacity(content.len());
+    let mut last = 0;
+    for (index, _) in lowercase.match_indices(&end) {
+        escaped += &content[last..=index];
+        escaped.push('\\');
+        last = index + 1;
+    }
+    escaped += &content[last..];
+    escaped.replace("<!--", "<\\!--")
+}
+
+fn integrity_attributes(integrity: &Option<String>) -> String {
+    match integrity {
+        Some(integrity) => format!(
+            " integrity=\"{}\" crossorigin=\"anonymous\"",
+            escape(integrity)
+        ),
+        None => String::new(),
+    }
+}
+
+/// JSON that is safe to embed in a `<script>` and to evaluate.
+pub(crate) fn script_json<T: Serialize + ?Sized>(value: &T) -> String {
+    serde_json::to_string(value)
+        .unwrap_or_default()
+        .replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+        .replace('&', "\\u0026")
+        .replace('\u{2028}', "\\u2028")
+        .replace('\u{2029}', "\\u2029")
+}
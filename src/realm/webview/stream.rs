@@ -0,0 +1,202 @@
+use std::panic::catch_unwind;
+use std::panic::resume_unwind;
+use std::panic::AssertUnwindSafe;
+
+use actix::prelude::*;
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::future::ready;
+use futures::future::select;
+use futures::future::BoxFuture;
+use futures::future::Either;
+use futures::prelude::*;
+use futures::stream::BoxStream;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::Api;
+
+use super::api::malformed_payload;
+use super::api::WebViewApi;
+use super::errors::ApiOutput;
+use super::ApiResponse;
+use super::ApiResponseBody;
+use super::ErrorKind;
+use super::InvokeBody;
+use super::InvokeRequest;
+use super::RequestId;
+
+/// Marks an api whose output is a `Stream` of `Partial` items instead of
+/// a single `Future`. Every item is sent through the parts of the request
+/// as it arrives and the request is answered once the stream ends.
+pub struct Streaming<API>(pub(crate) API);
+
+pub enum Partial<T> {
+    Chunk(T),
+    Progress(Progress),
+}
+
+#[derive(Serialize, Clone, Copy)]
+pub struct Progress {
+    pub done: u64,
+    pub total: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct StreamPart {
+    pub(crate) seq: u64,
+    pub(crate) done: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) progress: Option<Progress>,
+}
+
+fn part(
+    api_name: String,
+    id: Option<RequestId>,
+    part: StreamPart,
+    error: bool,
+    payload: Option<Value>,
+) -> ApiResponse {
+    ApiResponse::OpStream {
+        id,
+        api_name,
+        part,
+        body: ApiResponseBody {
+            error,
+            payload,
+            kind: if error { Some(ErrorKind::Api) } else { None },
+        },
+    }
+}
+
+impl<API, T> Handler<InvokeRequest> for WebViewApi<Streaming<API>>
+where
+    API: Api + Unpin + 'static,
+    API::Input: DeserializeOwned,
+    API::Output: Stream<Item = Partial<T>> + 'static,
+    T: ApiOutput,
+{
+    type Result = ResponseActFuture<Self, ApiResponse>;
+
+    fn handle(&mut self, msg: InvokeRequest, _: &mut Self::Context) -> Self::Result {
+        let InvokeRequest {
+            body:
+                InvokeBody {
+                    id,
+                    api_name,
+                    payload,
+                },
+            data,
+            parts,
+            route,
+            ..
+        } = msg;
+        let (name, request_id) = (api_name.clone(), id.clone());
+        let result = serde_json::from_value(payload.clone());
+        let handler = match result {
+            Ok(result) => {
+                let stream = catch_unwind(AssertUnwindSafe(|| self.api.0.handle(result)));
+                async move {
+                    let stream = stream.unwrap_or_else(|payload| resume_unwind(payload));
+                    futures::pin_mut!(stream);
+                    let mut seq = 0;
+                    while let Some(item) = stream.next().await {
+                        let response = match item {
+                            Partial::Chunk(chunk) => match chunk.into_result() {
+                                Err(err) => {
+                                    let done = StreamPart {
+                                        seq,
+                                        done: true,
+                                        progress: None,
+                                    };
+                                    let err = serde_json::to_value(err).ok();
+                                    return part(api_name, id, done, true, err);
+                                }
+                                Ok(value) => {
+                                    let chunk = StreamPart {
+                                        seq,
+                                        done: false,
+                                        progress: None,
+                                    };
+                                    part(api_name.clone(), id.clone(), chunk, false, Some(value))
+                                }
+                            },
+                            Partial::Progress(progress) => {
+                                let progress = StreamPart {
+                                    seq,
+                                    done: false,
+                                    progress: Some(progress),
+                                };
+                                part(api_name.clone(), id.clone(), progress, false, None)
+                            }
+                        };
+                        parts.unbounded_send(response).ok();
+                        seq += 1;
+                    }
+                    let done = StreamPart {
+                        seq,
+                        done: true,
+                        progress: None,
+                    };
+                    part(api_name, id, done, false, None)
+                }
+                .right_future()
+            }
+            Err(e) => ready(malformed_payload(api_name, id, &payload, e)).left_future(),
+        };
+        self.track(name, route, request_id, &data, handler)
+    }
+}
+
+enum Answering {
+    Pending(
+        UnboundedReceiver<ApiResponse>,
+        BoxFuture<'static, ApiResponse>,
+    ),
+    Draining(UnboundedReceiver<ApiResponse>, ApiResponse),
+    Answered,
+}
+
+/// The parts sent for a request, in order, then its response. Parts sent
+/// before the response was returned are never yielded after it.
+pub(crate) fn ordered(
+    parts: UnboundedReceiver<ApiResponse>,
+    response: BoxFuture<'static, ApiResponse>,
+) -> BoxStream<'static, ApiResponse> {
+    fn drain(
+        mut parts: UnboundedReceiver<ApiResponse>,
+        response: ApiResponse,
+    ) -> Option<(ApiResponse, Answering)> {
+        match parts.try_next() {
+            Ok(Some(part)) => Some((part, Answering::Draining(parts, response))),
+            _ => Some((response, Answering::Answered)),
+        }
+    }
+
+    stream::unfold(Answering::Pending(parts, response), |state| async move {
+        match state {
+            Answering::Pending(mut parts, response) => match select(parts.next(), response).await {
+                Either::Left((Some(part), response)) => {
+                    Some((part, Answering::Pending(parts, response)))
+                }
+                Either::Left((None, response)) => Some((response.await, Answering::Answered)),
+                Either::Right((response, _)) => drain(parts, response),
+            },
+            Answering::Draining(parts, response) => drain(parts, response),
+            Answering::Answered => None,
+        }
+    })
+    .boxed()
+}
+
+pub fn streaming<API>(api: API) -> Streaming<API> {
+    Streaming(api)
+}
+
+pub fn chunk<T>(chunk: T) -> Partial<T> {
+    Partial::Chunk(chunk)
+}
+
+pub fn progress<T>(done: u64, total: Option<u64>) -> Partial<T> {
+    Partial::Progress(Progress { done, total })
+}
@@ -0,0 +1,141 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::catch_unwind;
+use std::panic::resume_unwind;
+use std::panic::AssertUnwindSafe;
+
+use actix::prelude::*;
+use futures::future::lazy;
+use futures::future::Either;
+use futures::future::LocalBoxFuture;
+use futures::prelude::*;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use super::errors::failure;
+use super::success;
+use super::ApiResponse;
+use super::ErrorKind;
+use super::InvokeRequest;
+use super::RequestId;
+
+/// Whether an api actor is restarted after one of its handlers panicked.
+/// A restart drops the requests still in flight, and makes a `lazy` api
+/// run its factory again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Restart {
+    Always,
+    Limit(u32),
+    Never,
+}
+
+impl Default for Restart {
+    fn default() -> Self {
+        Restart::Always
+    }
+}
+
+impl Restart {
+    pub(crate) fn allows(self, restarts: u32) -> bool {
+        match self {
+            Restart::Always => true,
+            Restart::Limit(limit) => restarts < limit,
+            Restart::Never => false,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema, Clone, Default)]
+pub struct ApiStatus {
+    panics: u32,
+    restarts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_panic: Option<String>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) enum Supervision {
+    Panicked(String, String),
+    Restarted(String),
+}
+
+/// Counts the panics and restarts of every api, answered by `realm/status`.
+/// It is loaded in the `ActorContainer` next to the `EventBus`.
+#[derive(Default)]
+pub struct StatusBoard {
+    apis: HashMap<String, ApiStatus>,
+}
+
+impl Actor for StatusBoard {
+    type Context = Context<Self>;
+}
+
+impl Handler<Supervision> for StatusBoard {
+    type Result = ();
+
+    fn handle(&mut self, msg: Supervision, _: &mut Self::Context) -> Self::Result {
+        match msg {
+            Supervision::Panicked(api_key, message) => {
+                let status = self.apis.entry(api_key).or_default();
+                status.panics += 1;
+                status.last_panic = Some(message);
+            }
+            Supervision::Restarted(api_key) => {
+                self.apis.entry(api_key).or_default().restarts += 1;
+            }
+        }
+    }
+}
+
+impl Handler<InvokeRequest> for StatusBoard {
+    type Result = MessageResult<InvokeRequest>;
+
+    fn handle(&mut self, msg: InvokeRequest, _: &mut Self::Context) -> Self::Result {
+        let body = msg.body;
+        MessageResult(success(body.api_name, body.id, &self.apis))
+    }
+}
+
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => (*message).into(),
+            Err(_) => "Box<dyn Any>".into(),
+        },
+    }
+}
+
+pub(crate) fn panicked(api_name: String, id: Option<RequestId>, message: &str) -> ApiResponse {
+    failure(
+        api_name,
+        id,
+        ErrorKind::Panic,
+        format!("Api panicked: {}", message),
+    )
+}
+
+/// Calls `handle`, moving a panic into the returned future so that it is
+/// caught and reported like one raised while the future runs.
+pub(crate) fn defer_panic<F, Fut>(handle: F) -> Either<Fut, LocalBoxFuture<'static, Fut::Output>>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future,
+    Fut::Output: 'static,
+{
+    match catch_unwind(AssertUnwindSafe(handle)) {
+        Ok(future) => Either::Left(future),
+        Err(payload) => {
+            Either::Right(lazy(move |_| -> Fut::Output { resume_unwind(payload) }).boxed_local())
+        }
+    }
+}
+
+pub(crate) fn catch_panic<Fut: Future>(
+    future: Fut,
+) -> impl Future<Output = Result<Fut::Output, String>> {
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .map(|result| result.map_err(panic_message))
+}
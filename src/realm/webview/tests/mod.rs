@@ -1,57 +1,1155 @@
-#[actix_rt::test]
-async fn lazy_works() {
-    use futures::future::Ready;
-    use std::sync::Arc;
-    use std::time::Instant;
+use serde::Serialize;
+use serde_json::Value;
 
-    use actix::Arbiter;
-    use actix::System;
-    use futures::FutureExt;
+use super::app::AppBuilder;
+use super::headless::HeadlessApp;
+use crate::api;
+use crate::prelude::*;
 
-    use super::app::Received;
+fn double() -> impl KeyedActor {
+    web_view_api("api/double", api(|x: i32| ready(x * 2)))
+}
 
-    use super::app::AppBuilder;
-    use crate::api;
-    use crate::prelude::*;
-    use tokio_postgres::*;
+fn send<T: Serialize>(app: &HeadlessApp, api_name: &str, payload: &T) {
+    app.send(serde_json::to_string(&request(api_name.into(), payload)).unwrap());
+}
+
+async fn next_response(app: &mut HeadlessApp) -> Value {
+    let response = app.recv().await.expect("App closed its channel");
+    serde_json::from_str(&response).unwrap()
+}
 
-    let now = Instant::now();
+async fn ask<T: Serialize>(app: &mut HeadlessApp, api_name: &str, payload: &T) -> Value {
+    send(app, api_name, payload);
+    next_response(app).await
+}
 
-    fn launch_query(param: i32, client: Arc<Client>) -> Ready<String> {
+#[actix_rt::test]
+async fn lazy_works() {
+    use futures::future::Ready;
+
+    fn launch_query(param: i32) -> Ready<String> {
         ready(format!(
             "Returning {} + 1 = {result}",
             param,
             result = param + 1
         ))
-    };
+    }
     let builder = AppBuilder::<String>::new(1);
-    let webview = builder
-        .load(lazy("api/test", |container| async move {
-            let client = connect(
-                "host=localhost user=syfers password=KHearts358/2 dbname=db2database",
-                NoTls,
-            )
-            .map(|result| {
-                let (client, connection) = result.unwrap();
-                Arbiter::spawn(connection.then(|_| async {}));
-                Arc::new(client)
-            });
-            let client = client.await;
-            println!("Conection spawned");
-            api(move |msg| launch_query(msg, client.clone()))
-        }))
-        .content(Content::Html("Hello World".into()))
+    let mut app = builder
+        .load(lazy("api/test", |_| async move { api(launch_query) }))
+        .headless()
         .finish();
 
-    let addr = unsafe { webview.user_data().as_ptr().as_ref() }.unwrap();
-
     for x in 0..100 {
         let request = request("api/test".into(), &x as &i32);
-        let request: Received = serde_json::to_string(&request).unwrap().into();
-        addr.send(request).await;
+        let request = request.with_id(RequestId::Number(x as i64));
+        app.send(serde_json::to_string(&request).unwrap());
+    }
+
+    for _ in 0..100 {
+        let response = next_response(&mut app).await;
+        let x = response["id"].as_i64().unwrap();
+        assert_eq!(response["error"], false);
+        assert_eq!(
+            response["payload"],
+            format!("Returning {} + 1 = {}", x, x + 1)
+        );
+    }
+}
+
+#[actix_rt::test]
+async fn responses_echo_request_ids() {
+    let mut app = AppBuilder::<String>::new(1)
+        .load(double())
+        .headless()
+        .finish();
+
+    let body = request("api/double".into(), &21).with_id(RequestId::Number(1));
+    app.send(serde_json::to_string(&body).unwrap());
+    let response = next_response(&mut app).await;
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["payload"], 42);
+
+    let body = request("api/double".into(), &"x").with_id(RequestId::Text("bad".into()));
+    app.send(serde_json::to_string(&body).unwrap());
+    let response = next_response(&mut app).await;
+    assert_eq!(response["id"], "bad");
+    assert_eq!(response["error"], true);
+
+    assert_eq!(ask(&mut app, "api/double", &1).await.get("id"), None);
+
+    app.send(r#"{ "id": "first", "api_name": "api/missing", "payload": null }"#);
+    let response = next_response(&mut app).await;
+    assert_eq!(response["api_name"], "request/not-found");
+    assert_eq!(response["id"], "first");
+    assert_eq!(response["error"], true);
+
+    app.send(r#"{ "id": 7, "api_name": 3 }"#);
+    let response = next_response(&mut app).await;
+    assert_eq!(response["api_name"], "request/failed");
+    assert_eq!(response["id"], 7);
+}
+
+#[actix_rt::test]
+async fn middlewares_wrap_the_api() {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use super::middleware::Next;
+
+    let calls = Arc::new(Mutex::new(vec![]));
+    let (outer, inner) = (calls.clone(), calls.clone());
+    let mut app = AppBuilder::<String>::new(1)
+        .load(double())
+        .load(middleware(move |req: InvokeRequest, next: Next| {
+            outer.lock().unwrap().push("outer");
+            let rejected = req.body().payload().as_i64() == Some(0);
+            let api_name = req.body().api_name().clone();
+            async move {
+                if rejected {
+                    error(api_name, None, &"Zero is not allowed")
+                } else {
+                    next.call(req).await
+                }
+            }
+        }))
+        .load(middleware(move |req: InvokeRequest, next: Next| {
+            inner.lock().unwrap().push("inner");
+            next.call(req)
+                .map(|response| success("api/wrapped".into(), None, &response.body()))
+        }))
+        .headless()
+        .finish();
+
+    let response = ask(&mut app, "api/double", &21).await;
+    assert_eq!(response["api_name"], "api/wrapped");
+    assert_eq!(response["payload"], "42");
+    assert_eq!(*calls.lock().unwrap(), vec!["outer", "inner"]);
+
+    let response = ask(&mut app, "api/double", &0).await;
+    assert_eq!(response["error"], true);
+    assert_eq!(response["payload"], "Zero is not allowed");
+    assert_eq!(*calls.lock().unwrap(), vec!["outer", "inner", "outer"]);
+}
+
+#[actix_rt::test]
+async fn events_reach_subscribers_until_they_leave() {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use actix::prelude::*;
+    use serde_json::json;
+
+    use super::events::Subscribers;
+    use super::AppString;
+
+    struct Probe;
+
+    impl Actor for Probe {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<AppString> for Probe {
+        type Result = ();
+
+        fn handle(&mut self, _: AppString, ctx: &mut Self::Context) -> Self::Result {
+            ctx.stop();
+        }
+    }
+
+    let builder = AppBuilder::<String>::new(0);
+    let events = builder.events();
+    let mut app = builder.headless().finish();
+
+    let topic = json!({ "topic": "ranking" });
+    assert_eq!(
+        ask(&mut app, "realm/subscribe", &topic).await["payload"],
+        "ranking"
+    );
+    events.send(publish("ranking", &[1, 2])).await.unwrap();
+    let event = next_response(&mut app).await;
+    assert_eq!(event, json!({ "topic": "ranking", "payload": [1, 2] }));
+
+    ask(&mut app, "realm/unsubscribe", &topic).await;
+    events.send(publish("ranking", &3)).await.unwrap();
+    let response = ask(&mut app, "api/missing", &()).await;
+    assert_eq!(response["api_name"], "request/not-found");
+    assert_eq!(events.send(Subscribers("ranking".into())).await.unwrap(), 0);
+
+    let subscribe = InvokeRequest {
+        body: request("realm/subscribe".into(), &json!({ "topic": "ticks" })),
+        caller: Probe.start().recipient(),
+        data: Arc::new(HashMap::new().into()),
+        params: RouteParams::default(),
+        parts: futures::channel::mpsc::unbounded().0,
+        route: "realm/subscribe".into(),
+    };
+    events.send(subscribe).await.unwrap();
+    events.send(publish("ticks", &1)).await.unwrap();
+    assert_eq!(events.send(Subscribers("ticks".into())).await.unwrap(), 1);
+    tokio::time::delay_for(Duration::from_millis(10)).await;
+    events.send(publish("ticks", &2)).await.unwrap();
+    assert_eq!(events.send(Subscribers("ticks".into())).await.unwrap(), 0);
+}
+
+#[actix_rt::test]
+async fn streams_send_parts_before_the_answer() {
+    use futures::stream;
+
+    let rows = |count: u64| {
+        stream::once(ready(progress(0, Some(count))))
+            .chain(stream::iter(0..count).map(chunk))
+            .then(|part| tokio::task::yield_now().map(|_| part))
+            .boxed()
+    };
+    let mut app = AppBuilder::<String>::new(1)
+        .load(web_view_api("api/rows", streaming(api(rows))))
+        .devtools(1)
+        .headless()
+        .finish();
+
+    let body = request("api/rows".into(), &3).with_id(RequestId::Number(1));
+    app.send(serde_json::to_string(&body).unwrap());
+    let mut responses = vec![];
+    for _ in 0..5 {
+        responses.push(next_response(&mut app).await);
+    }
+    let seqs = responses.iter().map(|response| response["seq"].clone());
+    assert_eq!(seqs.collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+    assert_eq!(responses[0]["progress"]["total"], 3);
+    assert_eq!(responses[3]["payload"], 2);
+    assert!(responses.iter().all(|response| response["id"] == 1));
+    assert_eq!(responses[4]["done"], true);
+    assert!(responses[..4]
+        .iter()
+        .all(|response| response["done"] == false));
+
+    let requests = ask(&mut app, "realm/devtools/requests", &()).await["payload"].clone();
+    assert_eq!(requests[0]["parts"].as_array().unwrap().len(), 4);
+    assert_eq!(requests[0]["response"]["done"], true);
+    let metrics = ask(&mut app, "realm/metrics", &()).await["payload"].clone();
+    assert_eq!(metrics["api"]["api/rows"]["parts"], 4);
+    assert_eq!(metrics["api"]["api/rows"]["requests"], 1);
+}
+
+#[actix_rt::test]
+async fn pending_requests_time_out_or_cancel() {
+    use std::time::Duration;
+
+    use futures::future::pending;
+
+    let mut app = AppBuilder::<String>::new(2)
+        .load(
+            web_view_api("api/slow", api(|_: ()| pending::<()>()))
+                .timeout(Duration::from_millis(10)),
+        )
+        .load(web_view_api("api/never", api(|_: ()| pending::<()>())))
+        .load(lazy("api/starting", |_| async {
+            pending::<()>().await;
+            api(|_: ()| ready(()))
+        }))
+        .headless()
+        .finish();
+
+    let response = ask(&mut app, "api/slow", &()).await;
+    assert_eq!(response["error"], true);
+    assert_eq!(response["payload"]["code"], "timeout");
+    assert_eq!(response["payload"]["message"], "Request timed out");
+    assert_eq!(response["payload"]["retryable"], true);
+
+    let id = RequestId::Text("pending".into());
+    let never = request("api/never".into(), &()).with_id(id.clone());
+    app.send(serde_json::to_string(&never).unwrap());
+    tokio::time::delay_for(Duration::from_millis(10)).await;
+    app.send(serde_json::to_string(&never).unwrap());
+    let duplicate = next_response(&mut app).await;
+    assert_eq!(duplicate["payload"]["code"], "invalid_request");
+    assert_eq!(duplicate["payload"]["message"], "Duplicate request id");
+    let cancel = serde_json::json!({ "api_name": "api/never", "id": id });
+    send(&app, "realm/cancel", &cancel);
+
+    let mut responses = [next_response(&mut app).await, next_response(&mut app).await];
+    responses.sort_by_key(|response| response["api_name"].to_string());
+    assert_eq!(responses[0]["payload"]["code"], "cancelled");
+    assert_eq!(responses[0]["id"], "pending");
+    assert_eq!(responses[1]["api_name"], "realm/cancel");
+    assert_eq!(responses[1]["payload"], true);
+
+    let starting = request("api/starting".into(), &()).with_id(id.clone());
+    app.send(serde_json::to_string(&starting).unwrap());
+    tokio::time::delay_for(Duration::from_millis(10)).await;
+    let cancel = serde_json::json!({ "api_name": "api/starting", "id": id });
+    send(&app, "realm/cancel", &cancel);
+
+    let mut responses = [next_response(&mut app).await, next_response(&mut app).await];
+    responses.sort_by_key(|response| response["api_name"].to_string());
+    assert_eq!(responses[0]["payload"]["code"], "cancelled");
+    assert_eq!(responses[1]["payload"], true);
+}
+
+#[actix_rt::test]
+async fn json_rpc_maps_requests_and_errors() {
+    let mut app = AppBuilder::<String>::new(1)
+        .load(double())
+        .protocol(Protocol::JsonRpc)
+        .headless()
+        .finish();
+
+    app.send(r#"{ "jsonrpc": "2.0", "method": "api/double", "params": 21 }"#);
+    app.send(r#"{ "jsonrpc": "2.0", "method": "api/double", "params": 21, "id": 1 }"#);
+    let response = next_response(&mut app).await;
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["result"], 42);
+
+    app.send(r#"{ "jsonrpc": "2.0", "method": "api/missing", "id": 2 }"#);
+    let response = next_response(&mut app).await;
+    assert_eq!(response["id"], 2);
+    assert_eq!(response["error"]["code"], -32601);
+
+    app.send(r#"{ "jsonrpc": "2.0", "method": "api/double", "params": "x", "id": 3 }"#);
+    let response = next_response(&mut app).await;
+    assert_eq!(response["error"]["code"], -32602);
+
+    app.send("{");
+    let response = next_response(&mut app).await;
+    assert_eq!(response["id"], Value::Null);
+    assert_eq!(response["error"]["code"], -32700);
+}
+
+#[actix_rt::test]
+async fn batches_answer_combined_or_individually() {
+    let batch = r#"[
+        { "id": 1, "api_name": "api/double", "payload": 1 },
+        { "id": 2, "api_name": "api/missing", "payload": null },
+        { "id": 3, "api_name": "api/double", "payload": 3 }
+    ]"#;
+
+    let mut app = AppBuilder::<String>::new(1)
+        .load(double())
+        .headless()
+        .finish();
+
+    app.send(batch);
+    let response = next_response(&mut app).await;
+    let responses = response.as_array().unwrap();
+    assert_eq!(responses.len(), 3);
+    assert_eq!(responses[0]["payload"], 2);
+    assert_eq!(responses[1]["api_name"], "request/not-found");
+    assert_eq!(responses[1]["id"], 2);
+    assert_eq!(responses[2]["payload"], 6);
+
+    let mut app = AppBuilder::<String>::new(1)
+        .load(double())
+        .batch(Batch::Individual)
+        .headless()
+        .finish();
+
+    app.send(batch);
+    let mut responses = vec![];
+    for _ in 0..3 {
+        responses.push(next_response(&mut app).await);
+    }
+    responses.sort_by_key(|response| response["id"].as_i64());
+    assert_eq!(responses[0]["payload"], 2);
+    assert_eq!(responses[1]["error"], true);
+    assert_eq!(responses[2]["payload"], 6);
+}
+
+#[actix_rt::test]
+async fn describe_lists_api_schemas() {
+    let mut app = AppBuilder::<String>::new(1)
+        .load(double())
+        .headless()
+        .finish();
+
+    let response = ask(&mut app, "realm/describe", &()).await;
+    let schemas = &response["payload"];
+    assert_eq!(schemas["api/double"]["input"]["type"], "integer");
+    assert_eq!(schemas["api/double"]["output"]["type"], "integer");
+    assert_eq!(
+        schemas["realm/cancel"]["input"]["required"],
+        serde_json::json!(["api_name", "id"])
+    );
+}
+
+#[actix_rt::test]
+async fn results_are_classified_by_type() {
+    use schemars::JsonSchema;
+
+    #[derive(Serialize, JsonSchema)]
+    struct Row {
+        name: &'static str,
     }
 
-    System::current().stop();
+    impl ApiPayload for Row {}
+
+    let checked = |x: i32| {
+        ready(match x {
+            x if x >= 0 => Ok(x),
+            _ => Err(ApiError::new("negative", "Expected a positive number").details(&x)),
+        })
+    };
+    let mut app = AppBuilder::<String>::new(4)
+        .load(web_view_api("api/checked", api(checked)))
+        .load(web_view_api(
+            "api/shaped",
+            api(|_: ()| ready(serde_json::json!({ "Err": "Not an error" }))),
+        ))
+        .load(web_view_api(
+            "api/row",
+            api(|_: ()| ready(Row { name: "row" })),
+        ))
+        .load(web_view_api(
+            "api/untyped",
+            api(|_: ()| ready(Err::<(), _>("Not found"))),
+        ))
+        .headless()
+        .finish();
+
+    let response = ask(&mut app, "api/checked", &-1).await;
+    assert_eq!(response["error"], true);
+    assert_eq!(response["payload"]["code"], "negative");
+    assert_eq!(response["payload"]["details"], -1);
+    assert_eq!(response["payload"]["retryable"], false);
+
+    let response = ask(&mut app, "api/checked", &1).await;
+    assert_eq!(response["error"], false);
+    assert_eq!(response["payload"], 1);
+
+    let response = ask(&mut app, "api/shaped", &()).await;
+    assert_eq!(response["error"], false);
+
+    let response = ask(&mut app, "api/row", &()).await;
+    assert_eq!(response["payload"]["name"], "row");
+
+    let response = ask(&mut app, "api/untyped", &()).await;
+    assert_eq!(response["error"], true);
+    assert_eq!(response["payload"]["code"], "api_error");
+    assert_eq!(response["payload"]["message"], "Not found");
+
+    let schemas = ask(&mut app, "realm/describe", &()).await["payload"].clone();
+    assert_eq!(schemas["api/checked"]["output"]["type"], "integer");
+}
+
+#[actix_rt::test]
+async fn adapters_compose_apis() {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let flaky = api(move |x: i32| {
+        let call = counter.fetch_add(1, Ordering::SeqCst);
+        ready(match call % 3 {
+            2 => Ok(x),
+            _ => Err(ApiError::new("flaky", "Try again").retryable(true)),
+        })
+    });
+    let flaky = flaky
+        .retry(RetryPolicy::attempts(3).delay(Duration::from_millis(1)))
+        .cache(Duration::from_secs(60))
+        .validate(|x: &i32| match x {
+            0 => Err(ApiError::new("zero", "Zero is not allowed")),
+            _ => Ok(()),
+        });
+
+    assert_eq!(flaky.handle(7).await, Ok(7));
+    assert_eq!(flaky.handle(7).await, Ok(7));
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+    assert_eq!(flaky.handle(0).await.unwrap_err().code(), "zero");
+
+    let failing = api(|_: i32| ready(Err::<i32, _>(ApiError::new("down", "Unavailable"))));
+    let backup = api(|x: i32| ready(Ok::<_, ApiError>(x * 10)));
+    let slow = api(|_: i32| futures::future::pending::<Result<i32, ApiError>>());
+    let composed = failing
+        .fallback(backup)
+        .zip(api(|x: String| ready(Ok::<_, ApiError>(x))).map_input(|x: i32| x.to_string()))
+        .map_input(|x: u8| x as i32);
+    assert_eq!(composed.handle(4).await, Ok((40, "4".to_string())));
+
+    let slow = slow
+        .timeout(Duration::from_millis(5))
+        .map_err(|err: ApiError| err.code().to_string());
+    assert_eq!(slow.handle(1).await, Err("timeout".to_string()));
+}
+
+#[actix_rt::test]
+async fn context_apis_see_the_request() {
+    let handler = |x: i32, context: RequestContext| {
+        let found = context.actor::<EventBus>().is_some();
+        ready(serde_json::json!({
+            "x": x,
+            "api_name": context.api_name(),
+            "id": context.id(),
+            "found": found
+        }))
+    };
+    let mut app = AppBuilder::<String>::new(1)
+        .load(web_view_api("api/context", contextual(handler)))
+        .headless()
+        .finish();
+
+    let request = request("api/context".into(), &5).with_id(RequestId::Number(9));
+    app.send(serde_json::to_string(&request).unwrap());
+    let response = next_response(&mut app).await;
+    assert_eq!(response["payload"]["x"], 5);
+    assert_eq!(response["payload"]["api_name"], "api/context");
+    assert_eq!(response["payload"]["id"], 9);
+    assert_eq!(response["payload"]["found"], true);
+}
+
+#[actix_rt::test]
+async fn init_with_extracts_dependencies() {
+    use actix::prelude::*;
+
+    struct Missing;
+
+    impl Actor for Missing {
+        type Context = Context<Self>;
+    }
+
+    let built = AppBuilder::<String>::new(1)
+        .load(shared(String::from("Hello")))
+        .load(api_init_with(
+            |(greeting, events, missing): (
+                Shared<String>,
+                Addr<EventBus>,
+                Option<Addr<Missing>>,
+            )| {
+                assert!(missing.is_none());
+                let _ = events;
+                ready(web_view_api(
+                    "api/greet",
+                    api(move |name: String| ready(format!("{}, {}", *greeting, name))),
+                ))
+            },
+        ))
+        .await;
+    let built = built
+        .ok()
+        .unwrap()
+        .load(api_init_with(
+            |greeting: Shared<String>, _: Addr<EventBus>| {
+                ready(web_view_api(
+                    "api/shout",
+                    api(move |name: String| ready(format!("{}, {}!", *greeting, name))),
+                ))
+            },
+        ))
+        .await;
+    let mut app = built.ok().unwrap().headless().finish();
+    let response = ask(&mut app, "api/greet", &"realm").await;
+    assert_eq!(response["payload"], "Hello, realm");
+    let response = ask(&mut app, "api/shout", &"realm").await;
+    assert_eq!(response["payload"], "Hello, realm!");
+
+    let built = AppBuilder::<String>::new(1)
+        .load(
+            api_init_with(|_: Addr<Missing>, _: Shared<u8>| {
+                ready(web_view_api("api/never", api(|_: ()| ready(()))))
+            })
+            .key("api/never"),
+        )
+        .await;
+    let err = built.err().unwrap();
+    assert_eq!(err.api(), "api/never");
+    assert_eq!(err.missing_types().len(), 2);
+    assert!(err.missing_types()[0].contains("Missing"));
+    assert!(err.to_string().contains("Shared<u8>"));
+}
+
+#[actix_rt::test]
+async fn routes_match_patterns_and_prefixes() {
+    let report = |_: (), context: RequestContext| ready(context.param("name").map(String::from));
+    let rest = |_: (), context: RequestContext| ready(context.param("*").map(String::from));
+    let mut app = AppBuilder::<String>::new(3)
+        .load(web_view_api("api/report/{name}", contextual(report)))
+        .load(web_view_api(
+            "api/report/ranking",
+            api(|_: ()| ready("exact")),
+        ))
+        .load(web_view_api("api/files/*", contextual(rest)))
+        .load(
+            versioned(1)
+                .load(router("api/report").load(web_view_api("count", api(|x: i32| ready(x))))),
+        )
+        .headless()
+        .finish();
+
+    assert_eq!(
+        ask(&mut app, "api/report/sales", &()).await["payload"],
+        "sales"
+    );
+    assert_eq!(
+        ask(&mut app, "api/report/ranking", &()).await["payload"],
+        "exact"
+    );
+    assert_eq!(
+        ask(&mut app, "api/files/a/b.csv", &()).await["payload"],
+        "a/b.csv"
+    );
+    assert_eq!(ask(&mut app, "v1/api/report/count", &4).await["payload"], 4);
+    send(&app, "api/report/a/b", &());
+    assert_eq!(
+        next_response(&mut app).await["payload"]["code"],
+        "not_found"
+    );
+}
+
+#[actix_rt::test]
+async fn lazy_init_retries_and_reinitializes() {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let runs = Arc::new(AtomicUsize::new(0));
+    let invalidator = Invalidator::new();
+    let factory = {
+        let runs = runs.clone();
+        move |_| {
+            let run = runs.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match run {
+                    0 | 1 => Err(ApiError::new("connect", "Connection refused")),
+                    _ => Ok(api(move |x: usize| ready(x + run))),
+                }
+            }
+        }
+    };
+    let mut app = AppBuilder::<String>::new(2)
+        .load(
+            lazy("api/flaky", factory)
+                .retry(RetryPolicy::attempts(3).delay(Duration::from_millis(1)))
+                .invalidated_by(&invalidator),
+        )
+        .load(lazy("api/down", |_| async {
+            let down = ApiError::new("connect", "Connection refused");
+            Err(down).map(|()| api(|_: ()| ready(())))
+        }))
+        .headless()
+        .finish();
+
+    for x in 0..3 {
+        let request = request("api/flaky".into(), &x).with_id(RequestId::Number(x as i64));
+        app.send(serde_json::to_string(&request).unwrap());
+    }
+    for _ in 0..3 {
+        let response = next_response(&mut app).await;
+        assert_eq!(response["payload"], response["id"].as_i64().unwrap() + 2);
+    }
+    assert_eq!(runs.load(Ordering::SeqCst), 3);
+
+    invalidator.invalidate();
+    assert_eq!(ask(&mut app, "api/flaky", &0).await["payload"], 3);
+
+    let response = ask(&mut app, "api/down", &()).await;
+    assert_eq!(response["error"], true);
+    assert_eq!(response["payload"]["code"], "connect");
+}
+
+#[actix_rt::test]
+async fn panics_answer_errors_and_restart() {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    let fragile = |x: i32| {
+        assert!(x >= 0, "negative input");
+        ready(x)
+    };
+    let inits = Arc::new(AtomicUsize::new(0));
+    let factory = {
+        let inits = inits.clone();
+        move |_| {
+            inits.fetch_add(1, Ordering::SeqCst);
+            async move { api(|x: i32| ready(100 / x)) }
+        }
+    };
+    let mut app = AppBuilder::<String>::new(4)
+        .load(web_view_api("api/fragile", api(fragile)))
+        .load(web_view_api("api/never", api(fragile)).restart(Restart::Never))
+        .load(lazy("api/lazy", factory))
+        .load(versioned(1).load(web_view_api("api/fragile", api(fragile))))
+        .headless()
+        .finish();
+
+    let response = ask(&mut app, "api/fragile", &-1).await;
+    assert_eq!(response["error"], true);
+    assert_eq!(response["payload"]["code"], "panic");
+    assert!(response["payload"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("negative input"));
+    assert_eq!(ask(&mut app, "api/fragile", &1).await["payload"], 1);
+
+    assert_eq!(
+        ask(&mut app, "api/never", &-1).await["payload"]["code"],
+        "panic"
+    );
+    assert_eq!(ask(&mut app, "api/never", &2).await["payload"], 2);
+
+    assert_eq!(
+        ask(&mut app, "api/lazy", &0).await["payload"]["code"],
+        "panic"
+    );
+    assert_eq!(ask(&mut app, "api/lazy", &4).await["payload"], 25);
+    assert_eq!(inits.load(Ordering::SeqCst), 2);
+
+    assert_eq!(
+        ask(&mut app, "v1/api/fragile", &-1).await["payload"]["code"],
+        "panic"
+    );
+
+    let status = ask(&mut app, "realm/status", &()).await["payload"].clone();
+    assert_eq!(status["api/fragile"]["panics"], 1);
+    assert_eq!(status["api/fragile"]["restarts"], 1);
+    assert_eq!(status["api/never"]["panics"], 1);
+    assert_eq!(status["api/never"]["restarts"], 0);
+    assert_eq!(status["api/lazy"]["restarts"], 1);
+    assert_eq!(status["v1/api/fragile"]["panics"], 1);
+}
+
+#[actix_rt::test]
+async fn idle_webview_lets_the_actors_answer() {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use futures::future::select;
+    use futures::future::Either;
+
+    use super::app::drive;
+
+    let mut app = AppBuilder::<String>::new(1)
+        .load(double())
+        .headless()
+        .finish();
+    let (events, event_loop) = mpsc::channel();
+    let step = move || event_loop.recv().ok().map(Ok);
+    let wake = move || events.send(()).is_ok();
+
+    send(&app, "api/double", &21);
+    let response = Box::pin(next_response(&mut app));
+    let driving = Box::pin(drive(step, wake, Duration::from_millis(5)));
+    match select(response, driving).await {
+        Either::Left((response, _)) => assert_eq!(response["payload"], 42),
+        Either::Right(_) => panic!("The event loop closed before the answer"),
+    };
+}
+
+#[actix_rt::test]
+async fn shutdown_drains_and_closes() {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use actix::prelude::*;
+
+    struct Store(Arc<AtomicUsize>);
+
+    impl Actor for Store {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Close> for Store {
+        type Result = ();
+
+        fn handle(&mut self, _: Close, _: &mut Self::Context) -> Self::Result {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let (started, closed) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let (on_start, on_close) = (started.clone(), closed.clone());
+    let sleep = |millis: u64| tokio::time::delay_for(Duration::from_millis(millis)).map(|_| ());
+    let mut app = AppBuilder::<String>::new(1)
+        .load(web_view_api("api/sleep", api(sleep)))
+        .load(closable(Store(closed.clone()).start()))
+        .on_start(move |container| {
+            assert!(container.get::<Store>().is_some());
+            on_start.fetch_add(1, Ordering::SeqCst);
+            ready(())
+        })
+        .on_close(move |_| {
+            on_close.fetch_add(10, Ordering::SeqCst);
+            ready(())
+        })
+        .shutdown_timeout(Duration::from_millis(200))
+        .headless()
+        .finish();
+
+    ask(&mut app, "api/sleep", &0).await;
+    send(&app, "api/sleep", &50);
+    send(&app, "api/sleep", &10_000);
+    tokio::time::delay_for(Duration::from_millis(10)).await;
+
+    let report = app.shutdown().await;
+    assert_eq!(started.load(Ordering::SeqCst), 1);
+    assert_eq!(closed.load(Ordering::SeqCst), 11);
+    assert_eq!(report.handled, 3);
+    assert_eq!(report.drained, 1);
+    assert_eq!(report.abandoned, 1);
+    assert_eq!(report.closed, 1);
+}
+
+#[actix_rt::test]
+async fn metrics_count_requests_per_api() {
+    use std::collections::BTreeMap;
+
+    use actix::prelude::*;
+
+    struct Statements;
+
+    impl Actor for Statements {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Collect> for Statements {
+        type Result = MessageResult<Collect>;
+
+        fn handle(&mut self, _: Collect, _: &mut Self::Context) -> Self::Result {
+            let mut metrics = Metrics::default();
+            metrics.observe(std::time::Duration::from_millis(3), Outcome::Ok);
+            let statements: BTreeMap<_, _> = vec![("SELECT 1".to_string(), metrics)]
+                .into_iter()
+                .collect();
+            MessageResult(("statement".into(), statements))
+        }
+    }
+
+    let mut app = AppBuilder::<String>::new(1)
+        .load(double())
+        .load(metered(Statements.start()))
+        .headless()
+        .finish();
+
+    for payload in &[
+        serde_json::json!(1),
+        serde_json::json!(2),
+        serde_json::json!("three"),
+    ] {
+        ask(&mut app, "api/double", payload).await;
+    }
+    ask(&mut app, "api/missing", &()).await;
+
+    let path = std::env::temp_dir().join("realm-metrics-test.prom");
+    let dump = serde_json::json!({ "prometheus": path });
+    let metrics = ask(&mut app, "realm/metrics", &dump).await["payload"].clone();
+    let double = &metrics["api"]["api/double"];
+    assert_eq!(double["requests"], 3);
+    assert_eq!(double["errors"], 1);
+    assert_eq!(double["malformed"], 1);
+    assert_eq!(double["in_flight"], 0);
+    assert_eq!(double["latency"]["count"], 3);
+    assert_eq!(metrics["api"]["request/not-found"]["not_found"], 1);
+    assert_eq!(metrics["statement"]["SELECT 1"]["requests"], 1);
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+    assert!(text.contains("realm_api_requests_total{key=\"api/double\"} 3"));
+    assert!(
+        text.contains("realm_statement_latency_seconds_bucket{key=\"SELECT 1\",le=\"0.005\"} 1")
+    );
+}
+
+#[actix_rt::test]
+async fn metrics_share_a_series_per_route() {
+    let report = |_: (), context: RequestContext| ready(context.param("name").map(String::from));
+    let mut app = AppBuilder::<String>::new(1)
+        .load(web_view_api("api/report/{name}", contextual(report)))
+        .headless()
+        .finish();
+
+    ask(&mut app, "api/report/sales", &()).await;
+    ask(&mut app, "api/report/ranking", &()).await;
+
+    let metrics = ask(&mut app, "realm/metrics", &()).await["payload"].clone();
+    assert_eq!(metrics["api"]["api/report/{name}"]["requests"], 2);
+    assert!(metrics["api"].get("api/report/sales").is_none());
+    assert!(metrics["api"].get("api/report/ranking").is_none());
+}
+
+#[actix_rt::test]
+async fn recordings_replay_and_diff() {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let path = std::env::temp_dir().join("realm-recording-test.jsonl");
+    let count = Arc::new(AtomicUsize::new(0));
+    let counter = move |_: ()| ready(count.fetch_add(1, Ordering::SeqCst));
+    let mut app = AppBuilder::<String>::new(2)
+        .load(double())
+        .load(web_view_api("api/counter", api(counter)))
+        .record(&path)
+        .headless()
+        .finish();
+
+    ask(&mut app, "api/double", &2).await;
+    for id in 0..2 {
+        let body = request("api/counter".into(), &()).with_id(RequestId::Number(id));
+        app.send(serde_json::to_string(&body).unwrap());
+        next_response(&mut app).await;
+    }
+    tokio::time::delay_for(Duration::from_millis(50)).await;
+
+    let recording = read_recording(&path).unwrap();
+    assert_eq!(recording.len(), 6);
+    let report = app.replay(&path).await.unwrap();
+    std::fs::remove_file(&path).ok();
+    assert_eq!(report.replayed, 3);
+    assert_eq!(report.matched, 1);
+    assert_eq!(report.mismatches.len(), 2);
+    let mismatch = &report.mismatches[0];
+    assert_eq!(mismatch.api_name, "api/counter");
+    assert_eq!(
+        mismatch.differences,
+        vec![Difference {
+            path: "payload".into(),
+            expected: 0.into(),
+            actual: 2.into(),
+        }]
+    );
+}
+
+#[actix_rt::test]
+async fn recordings_keep_notifications() {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    let path = std::env::temp_dir().join("realm-recording-notifications-test.jsonl");
+    let builder = AppBuilder::<String>::new(0).record(&path);
+    let events = builder.events();
+    let mut app = builder.headless().finish();
+
+    ask(&mut app, "realm/subscribe", &json!({ "topic": "ranking" })).await;
+    events.send(publish("ranking", &[1, 2])).await.unwrap();
+    next_response(&mut app).await;
+    tokio::time::delay_for(Duration::from_millis(50)).await;
+
+    let recording = read_recording(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+    assert_eq!(recording.len(), 3);
+    match &recording[2] {
+        Recorded::Notification { message, .. } => assert_eq!(
+            serde_json::from_str::<Value>(message).unwrap(),
+            json!({ "topic": "ranking", "payload": [1, 2] })
+        ),
+        _ => panic!("expected a notification"),
+    }
+}
+
+#[actix_rt::test]
+async fn devtools_inspect_and_refire() {
+    let mut app = AppBuilder::<String>::new(2)
+        .load(double())
+        .load(lazy("api/lazy", |_| ready(api(|x: i32| ready(x + 1)))))
+        .load(shared(String::from("config")))
+        .devtools(2)
+        .headless()
+        .finish();
+
+    for (api_name, payload) in &[("api/double", 1), ("api/lazy", 1), ("api/double", 3)] {
+        ask(&mut app, api_name, payload).await;
+    }
+
+    let devtool = |app: &HeadlessApp, tool: &str, payload: Value| {
+        let body = request(format!("realm/devtools/{}", tool), &payload);
+        app.send(serde_json::to_string(&body).unwrap());
+    };
+    devtool(&app, "apis", Value::Null);
+    let apis = next_response(&mut app).await["payload"].clone();
+    assert!(apis
+        .as_array()
+        .unwrap()
+        .contains(&Value::from("realm/devtools/{tool}")));
+    devtool(&app, "actors", Value::Null);
+    let actors = next_response(&mut app).await["payload"].to_string();
+    assert!(actors.contains("Shared<alloc::string::String>"));
+    assert!(actors.contains("DevTools"));
+    devtool(&app, "lazy", Value::Null);
+    let lazy = next_response(&mut app).await["payload"].clone();
+    assert_eq!(lazy["api/lazy"]["state"], "ready");
+
+    devtool(&app, "requests", Value::Null);
+    let requests = next_response(&mut app).await["payload"].clone();
+    assert_eq!(requests.as_array().unwrap().len(), 2);
+    assert_eq!(requests[1]["api_name"], "api/double");
+    assert_eq!(requests[1]["response"]["payload"], 6);
+
+    let seq = requests[1]["seq"].clone();
+    devtool(&app, "refire", serde_json::json!({ "seq": seq }));
+    let refired = next_response(&mut app).await["payload"].clone();
+    assert_eq!(refired["response"]["payload"], 6);
+    devtool(&app, "refire", serde_json::json!({ "seq": 1 }));
+    assert_eq!(
+        next_response(&mut app).await["payload"]["code"],
+        "not_found"
+    );
+}
+
+#[cfg(feature = "http")]
+#[actix_rt::test]
+async fn http_serves_the_page_with_the_bridge_and_assets() {
+    use actix_web::test;
+    use web_view::Content;
+
+    let (routes, _) = AppBuilder::<String>::new(0)
+        .asset("icons.css", Asset::embed("icons.css", b"i {}"))
+        .http("127.0.0.1:0")
+        .routes()
+        .unwrap();
+    let mut service =
+        test::init_service(actix_web::App::new().configure(|config| routes.configure(config)))
+            .await;
+
+    let page = test::TestRequest::get().uri("/").to_request();
+    let page = String::from_utf8(test::read_response(&mut service, page).await.to_vec()).unwrap();
+    assert!(page.contains("new WebSocket(\"ws://\" + location.host + \"/ws\")"));
+    assert!(page.contains("window.sendToElm(JSON.parse(event.data))"));
+    assert!(page.contains("<style>i {}</style>"));
+
+    let css = test::TestRequest::get()
+        .uri("/assets/icons.css")
+        .to_request();
+    let css = test::call_service(&mut service, css).await;
+    assert_eq!(css.headers().get("content-type").unwrap(), "text/css");
+    assert_eq!(test::read_body(css).await, "i {}".as_bytes());
+    let missing = test::TestRequest::get()
+        .uri("/assets/missing.js")
+        .to_request();
+    assert_eq!(
+        test::call_service(&mut service, missing).await.status(),
+        404
+    );
+
+    let url = AppBuilder::<String>::new(0)
+        .content(Content::Url("https://example.com".into()))
+        .http("127.0.0.1:0")
+        .routes();
+    assert_eq!(url.err().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+
+    let hot_reload = AppBuilder::<String>::new(0)
+        .hot_reload(HotReload::new(|| Ok(String::new())))
+        .http("127.0.0.1:0")
+        .routes();
+    assert_eq!(
+        hot_reload.err().unwrap().kind(),
+        std::io::ErrorKind::InvalidInput
+    );
+}
+
+#[test]
+fn hot_reload_renders_changed_files() {
+    use std::time::Duration;
+
+    let path = std::env::temp_dir().join("realm-hot-reload-test.css");
+    std::fs::write(&path, "body {}").unwrap();
+    let watched = path.clone();
+    let mut reload = HotReload::new(move || {
+        let css = std::fs::read_to_string(&watched)?;
+        Ok(format!("<style>{}</style>", css))
+    })
+    .watch(&path)
+    .interval(Duration::from_millis(0));
+
+    assert!(reload.poll().is_none());
+    std::fs::write(&path, "body { margin: 0 }").unwrap();
+    let content = reload.poll().unwrap().unwrap();
+    assert_eq!(content, "<style>body { margin: 0 }</style>");
+    assert!(reload.poll().is_none());
+
+    std::fs::remove_file(&path).unwrap();
+    assert!(reload.poll().unwrap().is_err());
+}
+
+#[test]
+fn hot_reload_watches_on_its_own_thread() {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let path = std::env::temp_dir().join("realm-hot-reload-thread-test.css");
+    std::fs::write(&path, "body {}").unwrap();
+    let watched = path.clone();
+    let (sender, receiver) = mpsc::channel();
+    let watching = HotReload::new(move || std::fs::read_to_string(&watched))
+        .watch(&path)
+        .interval(Duration::from_millis(5))
+        .spawn(move |content| sender.send(content.unwrap()).is_ok());
+
+    std::fs::write(&path, "body { margin: 0 }").unwrap();
+    let content = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(content, "body { margin: 0 }");
+
+    drop(watching);
+    std::thread::sleep(Duration::from_millis(20));
+    std::fs::write(&path, "body { padding: 0 }").unwrap();
+    std::fs::remove_file(&path).ok();
+    assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+}
+
+#[test]
+fn assets_generate_an_offline_page() {
+    let font = Asset::embed("fonts/icons.woff2", b"woff2");
+    assert_eq!(font.mime(), "font/woff2");
+    assert_eq!(font.data_uri(), "data:font/woff2;base64,d29mZjI=");
+
+    let mut assets = Assets::default();
+    assets.insert("main.js", Asset::embed("main.js", b"init('</script>')"));
+    assets.insert(
+        "icons.css",
+        Asset::embed("icons.css", b"@font-face { src: url('icons.woff2') }"),
+    );
+    assets.insert("icons.woff2", font);
+    assets.insert("main.js", Asset::embed("main.js", b"init('</script>');"));
+
+    let page = assets.page();
+    assert!(page.contains("src: url(\"data:font/woff2;base64,d29mZjI=\")"));
+    assert!(page.contains("<script>init('<\\/script>');</script>"));
+    assert!(page.find("<style>").unwrap() < page.find("<div id=\"elm\">").unwrap());
+    assert!(page.find("<div id=\"elm\">").unwrap() < page.find("<script>").unwrap());
+    assert_eq!(assets.iter().count(), 3);
+    assert_eq!(assets.get("icons.woff2").unwrap().bytes(), b"woff2");
+}
+
+#[test]
+fn shell_renders_an_escaped_page() {
+    let mut assets = Assets::default();
+    assets.insert("elm.js", Asset::embed("elm.js", b"var Elm = {};"));
+    let shell = Shell::new()
+        .lang("es")
+        .title("Reports & <Ranking>")
+        .meta("description", "\"quoted\"")
+        .link_stylesheet("https://cdn.example/theme.css?a=1&b=2", Some("sha384-x"))
+        .style("p::after { content: '</STYLE>' }")
+        .module("Pages.Main")
+        .mount("app")
+        .init(serde_json::json!({ "title": "</script><!--", "year": 2020 }))
+        .script("app.ports.ready.send(null);");
+    let page = shell.render_with(&assets);
 
-    println!("Exercise took: {:?}", Instant::now().duration_since(now));
+    assert!(page.starts_with("<!DOCTYPE html>\n<html lang=\"es\">"));
+    assert!(page.contains("<title>Reports &amp; &lt;Ranking&gt;</title>"));
+    assert!(page.contains("<meta name=\"description\" content=\"&quot;quoted&quot;\">"));
+    assert!(page.contains(
+        "<link rel=\"stylesheet\" href=\"https://cdn.example/theme.css?a=1&amp;b=2\" \
+         integrity=\"sha384-x\" crossorigin=\"anonymous\">"
+    ));
+    assert!(page.contains("content: '<\\/STYLE>'"));
+    assert!(page.contains("<div id=\"app\"></div>"));
+    assert!(page.contains(
+        "var app = Elm[\"Pages\"][\"Main\"].init({ node: document.getElementById(\"app\"), \
+         flags: {\"title\":\"\\u003c/script\\u003e\\u003c!--\",\"year\":2020} });"
+    ));
+    let elm = page.find("var Elm").unwrap();
+    let init = page.find("var app").unwrap();
+    let ready = page.find("app.ports.ready").unwrap();
+    assert!(elm < init && init < ready);
 }
@@ -1,4 +1,6 @@
+use std::collections::BTreeMap;
 use std::marker::PhantomData;
+use std::time::Instant;
 
 use actix::prelude::*;
 use futures::future::ready;
@@ -9,6 +11,13 @@ use tokio_postgres::*;
 
 use actix::dev::*;
 
+use realm::webview::prelude::Close;
+use realm::webview::prelude::Collect;
+use realm::webview::prelude::Metrics;
+use realm::webview::prelude::Outcome;
+
+use crate::ReportError;
+
 #[derive(Message)]
 #[rtype(result = "Registered")]
 #[allow(dead_code)]
@@ -25,13 +34,19 @@ pub(crate) struct QueryStatement<R: 'static, S, F>
     pub(crate) F,
     PhantomData<R>,
     pub(crate) Vec<Box<dyn ToSql + Send + Sync>>,
+    pub(crate) String,
 );
 
-pub(crate) fn query_message<R, S, T>(s: S, f: T, params: Vec<Box<dyn ToSql + Send + Sync>>) -> QueryStatement<R, S, T>
+pub(crate) fn query_message<R, S, T>(
+    s: S,
+    f: T,
+    params: Vec<Box<dyn ToSql + Send + Sync>>,
+    sql: &str,
+) -> QueryStatement<R, S, T>
 where
     R: 'static
 {
-    QueryStatement(s, f, PhantomData, params)
+    QueryStatement(s, f, PhantomData, params, sql.into())
 }
 
 pub(crate) enum RegisterResponse<A> {
@@ -89,6 +104,7 @@ pub(crate) struct ExecutionResult<R>(pub(crate) Result<Vec<R>, Error>);
 
 pub struct ClientActor {
     client: Client,
+    statements: BTreeMap<String, Metrics>,
 }
 impl Actor for ClientActor {
     type Context = Context<Self>;
@@ -131,6 +147,24 @@ where
     }
 }
 
+/// Answers the metrics of every prepared statement, keyed by its SQL.
+impl Handler<Collect> for ClientActor {
+    type Result = MessageResult<Collect>;
+
+    fn handle(&mut self, _: Collect, _: &mut Self::Context) -> Self::Result {
+        MessageResult(("statement".into(), self.statements.clone()))
+    }
+}
+
+/// Drops the client, which ends the connection task of `start_client`.
+impl Handler<Close> for ClientActor {
+    type Result = ();
+
+    fn handle(&mut self, _: Close, ctx: &mut Self::Context) -> Self::Result {
+        ctx.stop();
+    }
+}
+
 impl<S, T, R> Handler<QueryStatement<R, S, T>> for ClientActor
 where
     S: ToStatement + 'static,
@@ -140,10 +174,12 @@ where
 
     fn handle(
         &mut self,
-        QueryStatement(stat, map, _, params): QueryStatement<R, S, T>,
+        QueryStatement(stat, map, _, params, sql): QueryStatement<R, S, T>,
         _: &mut Self::Context,
     ) -> Self::Result {
         let client: *const Client = &self.client;
+        let started = Instant::now();
+        self.statements.entry(sql.clone()).or_default().enter();
         Box::pin(
             async move {
                 let params = params
@@ -155,15 +191,33 @@ where
                     .map(|vec| vec.into_iter().map(map).collect::<Vec<R>>());
                 ExecutionResult(res)
             }
-            .actfuture(),
+            .into_actor(self)
+            .map(move |res, actor, _| {
+                let outcome = match res.0 {
+                    Ok(_) => Outcome::Ok,
+                    Err(_) => Outcome::Error,
+                };
+                let metrics = actor.statements.entry(sql).or_default();
+                metrics.observe(started.elapsed(), outcome);
+                res
+            }),
         )
     }
 }
 
 pub async fn start_client(conn: &'static str) -> Addr<ClientActor> {
-    let (client, connection) = tokio_postgres::connect(conn, NoTls).await.unwrap();
+    try_start_client(conn).await.unwrap()
+}
+
+/// Like `start_client`, but returns the connection error, e.g. to a `lazy`
+/// factory that retries it.
+pub async fn try_start_client(conn: &'static str) -> Result<Addr<ClientActor>, ReportError> {
+    let (client, connection) = tokio_postgres::connect(conn, NoTls)
+        .await
+        .map_err(ReportError::PgError)?;
     Arbiter::spawn(async move {
         connection.await.ok();
     });
-    ClientActor { client }.start()
+    let statements = BTreeMap::new();
+    Ok(ClientActor { client, statements }.start())
 }
@@ -1,3 +1,5 @@
+use realm::webview::prelude::ApiError;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::fmt::Display;
@@ -13,7 +15,7 @@ extern crate tokio_postgres;
 pub mod client_actor;
 pub mod report_api;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct QueryInfo {
     name: String,
     params: Value,
@@ -53,6 +55,24 @@ impl Display for ReportError {
 
 impl std::error::Error for ReportError {}
 
+impl From<ReportError> for ApiError {
+    fn from(err: ReportError) -> Self {
+        ApiError::from(&err)
+    }
+}
+
+impl From<&ReportError> for ApiError {
+    fn from(err: &ReportError) -> Self {
+        match err {
+            ReportError::PgError(err) => {
+                let sql_state = err.code().map(|state| state.code());
+                ApiError::new("report/postgres", err.to_string()).details(&sql_state)
+            }
+            ReportError::CustomError(message) => ApiError::new("report/failed", *message),
+        }
+    }
+}
+
 pub mod prelude {
     pub use crate::client_actor::*;
     pub use crate::report_api::*;
@@ -8,6 +8,7 @@ use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value;
 use serde_postgres::from_row;
 use tokio_postgres::{row::Row, types::ToSql, types::Type, Statement};
+use tracing::{field, Instrument};
 
 use crate::client_actor::ClientActor;
 use crate::client_actor::Register;
@@ -106,13 +107,16 @@ where
             .ok_or_else(|| ReportError::CustomError("Client not initialized"));
         let params = params(&msg.params)
             .ok_or_else(|| ReportError::CustomError("Parameters for query could not be parsed"));
+        let span = tracing::info_span!("report", sql = self.query, rows = field::Empty);
+        let counted = span.clone();
+        let sql = self.query;
         async move {
             ready(client)
                 .and_then(|client| {
                     ready(statement).and_then(move |statement| async move {
                         let result = ready(params)
                             .and_then(|params| async move {
-                                let request = query_message(statement, solver, params);
+                                let request = query_message(statement, solver, params, sql);
                                 client
                                     .send(request)
                                     .await
@@ -124,8 +128,13 @@ where
                         result
                     })
                 })
+                .inspect_ok(|rows| {
+                    counted.record("rows", &rows.len());
+                })
+                .inspect_err(|err| tracing::warn!(error = %err, "report failed"))
                 .await
         }
+        .instrument(span)
         .boxed()
     }
 }